@@ -0,0 +1,81 @@
+//! Compare sequential and parallel piece hashing on a synthetic dataset.
+//!
+//! The dataset is 2GiB split into 8 files by default, set `HASH_BENCH_SIZE` to the number of bytes
+//! to use another size and `HASH_BENCH_PARALLELISM` to the number of pieces hashed at once.
+//! Run with `cargo bench --bench hash_pieces`.
+
+use async_std::fs::File;
+use async_std::task::block_on;
+use std::env;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Instant;
+use torrent_rs::metainfo::{HashPieces, PIECE_SIZE_1M};
+
+const FILE_COUNT: u64 = 8;
+
+fn env_or(key: &str, default: u64) -> u64 {
+    env::var(key)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+fn main() {
+    let total_size = env_or("HASH_BENCH_SIZE", 2 << 30);
+    let parallelism = env_or("HASH_BENCH_PARALLELISM", 8) as usize;
+    let dir = tempfile::tempdir().unwrap();
+    let mut files: Vec<(PathBuf, u64)> = Vec::new();
+    let mut chunk = vec![0u8; 1 << 20];
+    for i in 0..FILE_COUNT {
+        // files of uneven sizes so that pieces span file boundaries
+        let length = total_size / FILE_COUNT + i * 12345;
+        let path = dir.path().join(i.to_string());
+        let mut file = std::fs::File::create(&path).unwrap();
+        let mut written = 0;
+        while written < length {
+            for byte in chunk.iter_mut() {
+                *byte = rand::random();
+            }
+            let n = chunk.len().min((length - written) as usize);
+            file.write_all(&chunk[..n]).unwrap();
+            written += n as u64;
+        }
+        files.push((path, length));
+    }
+    let size: u64 = files.iter().map(|(_, length)| length).sum();
+    let mib = size as f64 / (1 << 20) as f64;
+
+    block_on(async {
+        let start = Instant::now();
+        let mut readers = Vec::new();
+        for (path, _) in files.iter() {
+            readers.push(File::open(path).await.unwrap());
+        }
+        let sequential = HashPieces::hash_pieces(readers, PIECE_SIZE_1M)
+            .await
+            .unwrap();
+        let sequential_secs = start.elapsed().as_secs_f64();
+        println!(
+            "sequential: {:.0}MiB in {:.2}s, {:.0}MiB/s",
+            mib,
+            sequential_secs,
+            mib / sequential_secs
+        );
+
+        let start = Instant::now();
+        let parallel = HashPieces::hash_files_parallel(files.clone(), PIECE_SIZE_1M, parallelism)
+            .await
+            .unwrap();
+        let parallel_secs = start.elapsed().as_secs_f64();
+        println!(
+            "parallel x{}: {:.0}MiB in {:.2}s, {:.0}MiB/s, speedup {:.2}",
+            parallelism,
+            mib,
+            parallel_secs,
+            mib / parallel_secs,
+            sequential_secs / parallel_secs
+        );
+        assert_eq!(sequential, parallel);
+    });
+}
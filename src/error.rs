@@ -58,4 +58,10 @@ pub enum Error {
     DhtCallBackErr,
     #[error("TimeoutError")]
     TimeoutError(#[from] TimeoutError),
+    #[error("BencodeErr {0}")]
+    BencodeErr(#[from] serde_bencode::Error),
+    #[error("TrackerErr {0}")]
+    TrackerErr(#[from] crate::tracker::Error),
+    #[error("MetaInfoErr {0}")]
+    MetaInfoErr(#[from] crate::metainfo::Error),
 }
@@ -6,5 +6,8 @@ pub mod error;
 pub mod magnet;
 pub mod metainfo;
 pub mod peer_protocol;
+pub mod torrent;
+pub mod tracker;
 
 pub use error::Error;
+pub use torrent::Torrent;
@@ -1,5 +1,7 @@
+use crate::metainfo::Error as MetaInfoError;
 use data_encoding::DecodeError;
 use hex::FromHexError;
+use std::net::AddrParseError;
 use std::result;
 use thiserror::Error;
 use url::{ParseError as ParseUrlError, Url};
@@ -16,4 +18,8 @@ pub enum MagnetError {
     FromParseUrl(#[from] ParseUrlError),
     #[error("FromHex {0}")]
     FromHex(#[from] FromHexError),
+    #[error("Address {0}")]
+    Address(#[from] AddrParseError),
+    #[error("InfoHash {0}")]
+    InfoHash(#[from] MetaInfoError),
 }
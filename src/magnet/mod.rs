@@ -1,138 +1,606 @@
 //! This moduie implements magnet URI format defined in https://www.bittorrent.org/beps/bep_0009.html
 
 mod error;
+pub use error::MagnetError;
 
-// use crate::metainfo::{HashPiece, PeerAddress};
-// use crate::metainfo::{Info, MetaInfo};
-// use data_encoding::BASE32;
-// use error::{MagnetError, Result};
-// use hex;
-// use std::{
-//     convert::{TryFrom, TryInto},
-//     str::FromStr,
-// };
-// use url::Url;
-
-// const MAGNET: &'static str = "magnet";
-// const V1_PREFIX: &'static str = "urn:btih:";
-
-// /// a link on a web page only containing enough information to join the swarm
-// /// see bep 9
-// #[derive(Debug)]
-// pub struct MagnetLink {
-//     info_hash: HashPiece,
-//     /// The tracker url.
-//     trackers: Vec<Url>,
-//     /// The display name that may be used by the client to display while waiting for metadata.
-//     name: String,
-//     /// The peer address.
-//     peers: Vec<PeerAddress>,
-// }
-
-// impl From<Info> for MagnetLink {
-//     fn from(info: Info) -> Self {
-//         let trackers = Vec::new();
-//         let name = info.name.clone();
-//         let peers = Vec::new();
-//         let info_hash = info.into();
-//         Self {
-//             info_hash,
-//             trackers,
-//             name,
-//             peers,
-//         }
-//     }
-// }
-
-// impl From<MetaInfo> for MagnetLink {
-//     fn from(metainfo: MetaInfo) -> Self {
-//         let trackers = metainfo.get_trackers().unwrap();
-
-//         let name = metainfo.get_name().unwrap_or_else(|_| "".to_string());
-//         let info_hash = metainfo.info.into();
-//         let peers = metainfo.nodes;
-//         Self {
-//             info_hash,
-//             trackers,
-//             name,
-//             peers,
-//         }
-//     }
-// }
-
-// impl TryFrom<Url> for MagnetLink {
-//     type Error = MagnetError;
-//     fn try_from(value: Url) -> Result<Self> {
-//         if value.scheme() == MAGNET {
-//             let mut info_hash = HashPiece::default();
-//             let mut trackers = Vec::new();
-//             let mut name = String::new();
-//             let mut peers = Vec::new();
-//             for (key, val) in value.query_pairs() {
-//                 match key.as_ref() {
-//                     "xt" => {
-//                         if val.len() < V1_PREFIX.len() {
-//                             return Err(MagnetError::BrokenMagnetLink(value));
-//                         }
-//                         match &val[0..V1_PREFIX.len()] {
-//                             V1_PREFIX => {
-//                                 let encoded = &val[V1_PREFIX.len()..];
-//                                 if encoded.len() == 40 {
-//                                     hex::decode_to_slice(encoded, info_hash.as_mut())?;
-//                                 } else if encoded.len() == 32 {
-//                                     match BASE32.decode_mut(encoded.as_bytes(), info_hash.as_mut())
-//                                     {
-//                                         Ok(_) => {}
-//                                         Err(e) => return Err(MagnetError::from(e.error)),
-//                                     }
-//                                 } else {
-//                                     return Err(MagnetError::BrokenMagnetLink(value));
-//                                 }
-//                             }
-//                             _ => return Err(MagnetError::BrokenMagnetLink(value)),
-//                         }
-//                     }
-
-//                     "tr" => {
-//                         trackers.push(Url::from_str(val.as_ref())?);
-//                     }
-//                     "dn" => {
-//                         name = val.to_string();
-//                     }
-//                     "x.pe" => {
-//                         let peer = PeerAddress::from_str(val.as_ref())?;
-//                         peers.push(peer);
-//                     }
-//                     _ => {}
-//                 }
-//             }
-//             Ok(MagnetLink {
-//                 info_hash,
-//                 trackers,
-//                 name,
-//                 peers,
-//             })
-//         } else {
-//             Err(MagnetError::BrokenMagnetLink(value))
-//         }
-//     }
-// }
-
-// impl TryInto<Url> for MagnetLink {
-//     type Error = MagnetError;
-//     fn try_into(mut self) -> Result<Url> {
-//         let mut link = Url::parse(format!("{}:", MAGNET).as_str())?;
-//         let hex_hash = hex::encode(self.info_hash.as_mut());
-//         link.set_query(Some(format!("xt=urn:btih:{}", hex_hash).as_str()));
-//         let mut query_pairs = link.query_pairs_mut();
-//         query_pairs.append_pair("dn", &self.name);
-//         for track in self.trackers {
-//             query_pairs.append_pair("tr", track.as_str());
-//         }
-//         for peer in self.peers {
-//             query_pairs.append_pair("x.pe", &peer.to_string());
-//         }
-//         drop(query_pairs);
-//         Ok(link)
-//     }
-// }
+use crate::metainfo::{HashPiece, Info, MetaInfo, PeerAddress};
+use error::Result;
+use serde_bencode::ser::to_bytes;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashSet,
+    convert::{TryFrom, TryInto},
+    net::SocketAddr,
+    str::FromStr,
+};
+use url::Url;
+
+const MAGNET: &str = "magnet";
+const V1_PREFIX: &str = "urn:btih:";
+const V2_PREFIX: &str = "urn:btmh:";
+/// Multihash header of a SHA-256 hash: function code 0x12, digest length 0x20
+const SHA256_MULTIHASH: [u8; 2] = [0x12, 0x20];
+/// Largest number of file indices a link may select, ranges are expanded so they are bounded too
+const MAX_SELECT_ONLY_LEN: u64 = 1 << 20;
+
+/// a link on a web page only containing enough information to join the swarm
+/// see bep 9
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct MagnetLink {
+    /// The SHA1 info hash of a v1 or hybrid torrent.
+    info_hash: Option<HashPiece>,
+    /// The SHA-256 info hash of a v2 or hybrid torrent, see bep 52.
+    info_hash_v2: Option<[u8; 32]>,
+    /// The tracker url.
+    trackers: Vec<Url>,
+    /// The display name that may be used by the client to display while waiting for metadata.
+    name: String,
+    /// The peer address.
+    peers: Vec<PeerAddress>,
+    /// The web seed urls, see bep 19.
+    web_seeds: Vec<Url>,
+    /// The indices of the files to download, see bep 53.
+    select_only: Vec<u32>,
+}
+
+impl MagnetLink {
+    /// Create a link to the torrent of the v1 `info_hash`.
+    pub fn new<S: Into<String>>(
+        info_hash: HashPiece,
+        name: S,
+        trackers: Vec<Url>,
+        peers: Vec<PeerAddress>,
+    ) -> Self {
+        let mut link = Self::builder(info_hash).name(name).build();
+        link.trackers = trackers;
+        link.peers = peers;
+        link
+    }
+
+    /// Start building a link to the torrent of the v1 `info_hash`.
+    pub fn builder(info_hash: HashPiece) -> MagnetLinkBuilder {
+        MagnetLinkBuilder {
+            link: MagnetLink {
+                info_hash: Some(info_hash),
+                info_hash_v2: None,
+                trackers: Vec::new(),
+                name: String::new(),
+                peers: Vec::new(),
+                web_seeds: Vec::new(),
+                select_only: Vec::new(),
+            },
+        }
+    }
+
+    pub fn info_hash(&self) -> Option<&HashPiece> {
+        self.info_hash.as_ref()
+    }
+
+    pub fn info_hash_v2(&self) -> Option<&[u8; 32]> {
+        self.info_hash_v2.as_ref()
+    }
+
+    pub fn trackers(&self) -> &[Url] {
+        &self.trackers
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn peers(&self) -> &[PeerAddress] {
+        &self.peers
+    }
+
+    pub fn web_seeds(&self) -> &[Url] {
+        &self.web_seeds
+    }
+
+    pub fn select_only(&self) -> &[u32] {
+        &self.select_only
+    }
+
+    /// Does `info`, e.g. fetched from a peer with ut_metadata, match the info hashes of the link?
+    /// Every hash the link carries must match.
+    pub fn verify_info(&self, info: &Info) -> bool {
+        if self.info_hash.is_none() && self.info_hash_v2.is_none() {
+            return false;
+        }
+        let buf = match to_bytes(info) {
+            Ok(buf) => buf,
+            Err(_) => return false,
+        };
+        let v1_matched = match &self.info_hash {
+            Some(info_hash) => *info_hash == HashPiece::from(buf.as_slice()),
+            None => true,
+        };
+        let v2_matched = match &self.info_hash_v2 {
+            Some(info_hash) => info_hash[..] == Sha256::digest(&buf)[..],
+            None => true,
+        };
+        v1_matched && v2_matched
+    }
+}
+
+/// MagnetLinkBuilder creates a [`MagnetLink`] from its parts.
+#[derive(Debug)]
+pub struct MagnetLinkBuilder {
+    link: MagnetLink,
+}
+
+impl MagnetLinkBuilder {
+    /// Add the v2 info hash of a hybrid torrent.
+    pub fn info_hash_v2(mut self, info_hash_v2: [u8; 32]) -> Self {
+        self.link.info_hash_v2 = Some(info_hash_v2);
+        self
+    }
+
+    pub fn tracker(mut self, url: Url) -> Self {
+        self.link.trackers.push(url);
+        self
+    }
+
+    pub fn name<S: Into<String>>(mut self, name: S) -> Self {
+        self.link.name = name.into();
+        self
+    }
+
+    pub fn peer(mut self, peer: PeerAddress) -> Self {
+        self.link.peers.push(peer);
+        self
+    }
+
+    pub fn web_seed(mut self, url: Url) -> Self {
+        self.link.web_seeds.push(url);
+        self
+    }
+
+    pub fn select_only(mut self, indices: Vec<u32>) -> Self {
+        self.link.select_only = indices;
+        self
+    }
+
+    pub fn build(self) -> MagnetLink {
+        self.link
+    }
+}
+
+impl From<Info> for MagnetLink {
+    fn from(info: Info) -> Self {
+        let trackers = Vec::new();
+        let name = info.name.clone();
+        let peers = Vec::new();
+        let info_hash = Some((&info).into());
+        Self {
+            info_hash,
+            info_hash_v2: None,
+            trackers,
+            name,
+            peers,
+            web_seeds: Vec::new(),
+            select_only: Vec::new(),
+        }
+    }
+}
+
+/// Trackers of every tier in order like `MetaInfo::get_trackers`,
+/// but a tracker that doesn't parse is skipped rather than failing all of them.
+fn parse_trackers(metainfo: &MetaInfo) -> Vec<Url> {
+    let urls: Vec<&String> = if metainfo.announce_list.is_empty() {
+        metainfo.announce.iter().collect()
+    } else {
+        metainfo.announce_list.iter().flatten().collect()
+    };
+    let mut seen = HashSet::new();
+    urls.into_iter()
+        .filter(|url| seen.insert(*url))
+        .filter_map(|url| Url::parse(url).ok())
+        .collect()
+}
+
+impl From<MetaInfo> for MagnetLink {
+    fn from(metainfo: MetaInfo) -> Self {
+        (&metainfo).into()
+    }
+}
+
+impl From<&MetaInfo> for MagnetLink {
+    fn from(metainfo: &MetaInfo) -> Self {
+        let trackers = parse_trackers(metainfo);
+        let name = metainfo.get_name();
+        // a v2 only torrent has no SHA1 info hash
+        let info_hash = if !metainfo.is_v2() || metainfo.is_hybrid() {
+            Some(metainfo.info_hash())
+        } else {
+            None
+        };
+        let info_hash_v2 = metainfo.info_hash_v2().ok().flatten();
+        let web_seeds = metainfo
+            .url_list
+            .as_ref()
+            .map(|url_list| url_list.urls().to_vec())
+            .unwrap_or_default();
+        let peers = metainfo.nodes.clone();
+        Self {
+            info_hash,
+            info_hash_v2,
+            trackers,
+            name,
+            peers,
+            web_seeds,
+            select_only: Vec::new(),
+        }
+    }
+}
+
+/// Decode the hex or base32 encoded SHA1 hash of `urn:btih:`.
+fn decode_v1(encoded: &str, value: &Url) -> Result<HashPiece> {
+    let info_hash = match encoded.len() {
+        40 => HashPiece::from_hex(encoded)?,
+        32 => HashPiece::from_base32(encoded)?,
+        _ => return Err(MagnetError::BrokenMagnetLink(value.clone())),
+    };
+    Ok(info_hash)
+}
+
+/// Decode the hex encoded SHA-256 multihash of `urn:btmh:`.
+fn decode_v2(encoded: &str, value: &Url) -> Result<[u8; 32]> {
+    let multihash = hex::decode(encoded)?;
+    if multihash.len() != SHA256_MULTIHASH.len() + 32 || multihash[..2] != SHA256_MULTIHASH {
+        return Err(MagnetError::BrokenMagnetLink(value.clone()));
+    }
+    let mut info_hash = [0u8; 32];
+    info_hash.copy_from_slice(&multihash[2..]);
+    Ok(info_hash)
+}
+
+/// Store `hash`, a link carrying two different hashes of the same version is broken.
+fn set_once<T: PartialEq>(slot: &mut Option<T>, hash: T, value: &Url) -> Result<()> {
+    match slot {
+        Some(old) if *old != hash => Err(MagnetError::BrokenMagnetLink(value.clone())),
+        _ => {
+            *slot = Some(hash);
+            Ok(())
+        }
+    }
+}
+
+/// Parse a list of file indices and inclusive ranges such as `0,2,4-6` into `indices`,
+/// a link selecting more than `MAX_SELECT_ONLY_LEN` indices is broken.
+fn parse_select_only(val: &str, value: &Url, indices: &mut Vec<u32>) -> Result<()> {
+    let broken = || MagnetError::BrokenMagnetLink(value.clone());
+    for item in val.split(',') {
+        match item.split_once('-') {
+            Some((start, end)) => {
+                let start: u32 = start.parse().map_err(|_| broken())?;
+                let end: u32 = end.parse().map_err(|_| broken())?;
+                if start > end
+                    || indices.len() as u64 + (end - start) as u64 + 1 > MAX_SELECT_ONLY_LEN
+                {
+                    return Err(broken());
+                }
+                indices.extend(start..=end);
+            }
+            None => {
+                if indices.len() as u64 >= MAX_SELECT_ONLY_LEN {
+                    return Err(broken());
+                }
+                indices.push(item.parse().map_err(|_| broken())?);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Format file indices, runs of consecutive indices are written as ranges.
+fn format_select_only(indices: &[u32]) -> String {
+    let mut items = Vec::new();
+    let mut i = 0;
+    while i < indices.len() {
+        let start = indices[i];
+        while i + 1 < indices.len() && indices[i].checked_add(1) == Some(indices[i + 1]) {
+            i += 1;
+        }
+        if indices[i] == start {
+            items.push(start.to_string());
+        } else {
+            items.push(format!("{}-{}", start, indices[i]));
+        }
+        i += 1;
+    }
+    items.join(",")
+}
+
+impl TryFrom<Url> for MagnetLink {
+    type Error = MagnetError;
+    fn try_from(value: Url) -> Result<Self> {
+        if value.scheme() == MAGNET {
+            let mut info_hash = None;
+            let mut info_hash_v2 = None;
+            let mut trackers = Vec::new();
+            let mut name = String::new();
+            let mut peers = Vec::new();
+            let mut web_seeds = Vec::new();
+            let mut select_only = Vec::new();
+            for (key, val) in value.query_pairs() {
+                match key.as_ref() {
+                    "xt" => {
+                        if let Some(encoded) = val.strip_prefix(V1_PREFIX) {
+                            set_once(&mut info_hash, decode_v1(encoded, &value)?, &value)?;
+                        } else if let Some(encoded) = val.strip_prefix(V2_PREFIX) {
+                            set_once(&mut info_hash_v2, decode_v2(encoded, &value)?, &value)?;
+                        }
+                        // other urn types are not bittorrent hashes
+                    }
+                    "tr" => {
+                        trackers.push(Url::from_str(val.as_ref())?);
+                    }
+                    "dn" => {
+                        name = val.to_string();
+                    }
+                    "x.pe" => {
+                        let peer = PeerAddress(SocketAddr::from_str(val.as_ref())?);
+                        peers.push(peer);
+                    }
+                    "ws" => {
+                        web_seeds.push(Url::from_str(val.as_ref())?);
+                    }
+                    "so" => {
+                        parse_select_only(val.as_ref(), &value, &mut select_only)?;
+                    }
+                    _ => {}
+                }
+            }
+            if info_hash.is_none() && info_hash_v2.is_none() {
+                return Err(MagnetError::BrokenMagnetLink(value));
+            }
+            Ok(MagnetLink {
+                info_hash,
+                info_hash_v2,
+                trackers,
+                name,
+                peers,
+                web_seeds,
+                select_only,
+            })
+        } else {
+            Err(MagnetError::BrokenMagnetLink(value))
+        }
+    }
+}
+
+impl TryInto<Url> for MagnetLink {
+    type Error = MagnetError;
+    fn try_into(self) -> Result<Url> {
+        let mut link = Url::parse(format!("{}:", MAGNET).as_str())?;
+        // the urns are conventionally written without escaping the colons
+        let mut exact_topics = Vec::new();
+        if let Some(info_hash) = &self.info_hash {
+            exact_topics.push(format!("xt={}{}", V1_PREFIX, info_hash));
+        }
+        if let Some(info_hash_v2) = &self.info_hash_v2 {
+            let multihash = hex::encode([&SHA256_MULTIHASH[..], &info_hash_v2[..]].concat());
+            exact_topics.push(format!("xt={}{}", V2_PREFIX, multihash));
+        }
+        link.set_query(Some(&exact_topics.join("&")));
+        let mut query_pairs = link.query_pairs_mut();
+        if !self.name.is_empty() {
+            query_pairs.append_pair("dn", &self.name);
+        }
+        for track in self.trackers {
+            query_pairs.append_pair("tr", track.as_str());
+        }
+        for peer in self.peers {
+            query_pairs.append_pair("x.pe", &peer.0.to_string());
+        }
+        for web_seed in self.web_seeds {
+            query_pairs.append_pair("ws", web_seed.as_str());
+        }
+        if !self.select_only.is_empty() {
+            query_pairs.append_pair("so", &format_select_only(&self.select_only));
+        }
+        drop(query_pairs);
+        Ok(link)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_magnet_link() {
+        let url = Url::parse(
+            "magnet:?xt=urn:btih:c12fe1c06bba254a9dc9f519b335aa7c1367a88a\
+             &dn=debian&tr=http%3A%2F%2Ftracker.example.com%2Fannounce&x.pe=1.2.3.4%3A6881",
+        )
+        .unwrap();
+        let link = MagnetLink::try_from(url.clone()).unwrap();
+        assert_eq!(
+            hex::encode(link.info_hash.as_ref().unwrap()),
+            "c12fe1c06bba254a9dc9f519b335aa7c1367a88a"
+        );
+        assert!(link.info_hash_v2.is_none());
+        assert_eq!(link.name, "debian");
+        assert_eq!(link.trackers.len(), 1);
+        assert_eq!(
+            link.peers,
+            vec![PeerAddress("1.2.3.4:6881".parse().unwrap())]
+        );
+        let encoded: Url = link.try_into().unwrap();
+        assert_eq!(encoded, url);
+
+        let base32 = Url::parse("magnet:?xt=urn:btih:YEX6DQDLXISUVHOJ6UM3GNNKPQJWPKEK").unwrap();
+        let link = MagnetLink::try_from(base32).unwrap();
+        assert_eq!(
+            hex::encode(link.info_hash.unwrap()),
+            "c12fe1c06bba254a9dc9f519b335aa7c1367a88a"
+        );
+
+        assert!(MagnetLink::try_from(Url::parse("magnet:?dn=debian").unwrap()).is_err());
+        assert!(MagnetLink::try_from(Url::parse("magnet:?xt=urn:btih:c12f").unwrap()).is_err());
+        assert!(MagnetLink::try_from(Url::parse("http://example.com/").unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_magnet_link_web_seeds() {
+        let url = Url::parse(
+            "magnet:?xt=urn:btih:c12fe1c06bba254a9dc9f519b335aa7c1367a88a\
+             &ws=http%3A%2F%2Fx%2F&so=0%2C2-3",
+        )
+        .unwrap();
+        let link = MagnetLink::try_from(url.clone()).unwrap();
+        assert_eq!(link.web_seeds, vec![Url::parse("http://x/").unwrap()]);
+        assert_eq!(link.select_only, vec![0, 2, 3]);
+        let encoded: Url = link.try_into().unwrap();
+        assert_eq!(encoded, url);
+
+        let url =
+            Url::parse("magnet:?xt=urn:btih:c12fe1c06bba254a9dc9f519b335aa7c1367a88a&so=0,2,4-6")
+                .unwrap();
+        let link = MagnetLink::try_from(url).unwrap();
+        assert_eq!(link.select_only, vec![0, 2, 4, 5, 6]);
+        assert_eq!(format_select_only(&link.select_only), "0,2,4-6");
+        assert_eq!(
+            format_select_only(&[1, 4294967294, 4294967295]),
+            "1,4294967294-4294967295"
+        );
+        for so in [
+            "",
+            "1,",
+            "3-1",
+            "a-2",
+            "0-4294967295",
+            "0-1048574,1048575,1048576",
+        ] {
+            let url = Url::parse(&format!(
+                "magnet:?xt=urn:btih:c12fe1c06bba254a9dc9f519b335aa7c1367a88a&so={}",
+                so
+            ))
+            .unwrap();
+            assert!(MagnetLink::try_from(url).is_err());
+        }
+    }
+
+    #[test]
+    fn test_magnet_link_builder() {
+        let info_hash = HashPiece::rand_new();
+        let tracker = Url::parse("udp://tracker.example.com:6969").unwrap();
+        let peer = PeerAddress("[2001:db8::1]:6881".parse().unwrap());
+        let link = MagnetLink::builder(info_hash.clone())
+            .tracker(tracker.clone())
+            .name("debian")
+            .peer(peer.clone())
+            .build();
+        assert_eq!(link.info_hash(), Some(&info_hash));
+        assert_eq!(link.info_hash_v2(), None);
+        assert_eq!(link.trackers(), &[tracker][..]);
+        assert_eq!(link.name(), "debian");
+        assert_eq!(link.peers(), &[peer][..]);
+
+        let encoded: Url = link.clone().try_into().unwrap();
+        assert!(encoded.as_str().starts_with(&format!(
+            "magnet:?xt=urn:btih:{}&dn=debian&tr=",
+            hex::encode(&info_hash)
+        )));
+        assert_eq!(MagnetLink::try_from(encoded).unwrap(), link);
+    }
+
+    #[test]
+    fn test_magnet_link_new() {
+        let info_hash = HashPiece::rand_new();
+        let trackers = vec![
+            Url::parse("udp://tracker.example.com:6969").unwrap(),
+            Url::parse("http://tracker.example.org/announce").unwrap(),
+        ];
+        let link = MagnetLink::new(info_hash.clone(), "debian", trackers.clone(), Vec::new());
+        assert_eq!(link.info_hash(), Some(&info_hash));
+        assert_eq!(link.trackers(), &trackers[..]);
+        assert_eq!(link.name(), "debian");
+        assert!(link.peers().is_empty());
+
+        let encoded: Url = link.clone().try_into().unwrap();
+        assert_eq!(
+            encoded.query_pairs().filter(|(key, _)| key == "tr").count(),
+            2
+        );
+        assert_eq!(MagnetLink::try_from(encoded).unwrap(), link);
+    }
+
+    #[test]
+    fn test_magnet_link_hybrid() {
+        let url = Url::parse(
+            "magnet:?xt=urn:btih:b0d96589508010b9deb2b5b7806d9ce117e5eb28\
+             &xt=urn:btmh:122053c2fe0bab0462f6fbbfa51c005bb4463f4d18124ecfb090fbc97da9fae3baff",
+        )
+        .unwrap();
+        let link = MagnetLink::try_from(url.clone()).unwrap();
+        assert!(link.info_hash.is_some());
+        assert_eq!(
+            hex::encode(link.info_hash_v2.unwrap()),
+            "53c2fe0bab0462f6fbbfa51c005bb4463f4d18124ecfb090fbc97da9fae3baff"
+        );
+        let encoded: Url = link.try_into().unwrap();
+        assert_eq!(encoded, url);
+
+        let raw_torrent = include_bytes!("../metainfo/example/hybrid.torrent");
+        let meta_info = MetaInfo::from_bytes(raw_torrent).unwrap();
+        let link = MagnetLink::from(&meta_info);
+        assert_eq!(link, MagnetLink::from(meta_info));
+        assert_eq!(link.trackers.len(), 1);
+        let encoded: Url = link.try_into().unwrap();
+        assert_eq!(MagnetLink::try_from(encoded.clone()).unwrap().name, "a.txt");
+
+        // an invalid tracker doesn't drop the others
+        let mut invalid = MetaInfo::from_bytes(raw_torrent).unwrap();
+        invalid.announce_list = vec![
+            vec![
+                "not a url".to_string(),
+                "http://a.example/announce".to_string(),
+            ],
+            vec![
+                "udp://b.example:6969".to_string(),
+                "http://a.example/announce".to_string(),
+            ],
+        ];
+        assert!(invalid.get_trackers().is_err());
+        assert_eq!(
+            MagnetLink::from(&invalid).trackers(),
+            &[
+                Url::parse("http://a.example/announce").unwrap(),
+                Url::parse("udp://b.example:6969").unwrap()
+            ][..]
+        );
+        assert!(encoded.as_str().starts_with(url.as_str()));
+
+        // two different v1 hashes
+        let conflict = Url::parse(
+            "magnet:?xt=urn:btih:b0d96589508010b9deb2b5b7806d9ce117e5eb28\
+             &xt=urn:btih:c12fe1c06bba254a9dc9f519b335aa7c1367a88a",
+        )
+        .unwrap();
+        assert!(MagnetLink::try_from(conflict).is_err());
+    }
+
+    #[test]
+    fn test_magnet_link_verify_info() {
+        let raw_torrent = include_bytes!("../metainfo/example/hybrid.torrent");
+        let meta_info = MetaInfo::from_bytes(raw_torrent).unwrap();
+        let mut info = meta_info.info.clone();
+        let link = MagnetLink::from(meta_info);
+        let v1_only = MagnetLink::builder(link.info_hash.clone().unwrap()).build();
+        let mut v2_only = link.clone();
+        v2_only.info_hash = None;
+        assert!(link.verify_info(&info));
+        assert!(v1_only.verify_info(&info));
+        assert!(v2_only.verify_info(&info));
+        assert!(!MagnetLink::builder(HashPiece::rand_new())
+            .build()
+            .verify_info(&info));
+
+        info.name = "b.txt".to_string();
+        assert!(!link.verify_info(&info));
+        assert!(!v1_only.verify_info(&info));
+        assert!(!v2_only.verify_info(&info));
+    }
+}
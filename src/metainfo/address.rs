@@ -1,9 +1,11 @@
+use super::error::{Error, Result as MetaResult};
 use serde::{
     de::{Error as DeError, SeqAccess, Visitor},
     Deserialize, Deserializer, Serialize, Serializer,
 };
+use std::convert::TryInto;
 use std::fmt;
-use std::net::{IpAddr, SocketAddr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 
 pub(crate) const ADDRESS_V4_LEN: usize = 6;
 pub(crate) const ADDRESS_V6_LEN: usize = 18;
@@ -55,6 +57,54 @@ impl<'de> Deserialize<'de> for PeerAddress {
     }
 }
 
+impl PeerAddress {
+    /// Encode the address in compact form: the big-endian ip
+    /// (4 bytes for IPv4, 16 bytes for IPv6) followed by the port.
+    pub fn to_compact(&self) -> Vec<u8> {
+        let mut buf = match self.0.ip() {
+            IpAddr::V4(v4) => v4.octets().to_vec(),
+            IpAddr::V6(v6) => v6.octets().to_vec(),
+        };
+        buf.extend_from_slice(&self.0.port().to_be_bytes());
+        buf
+    }
+
+    /// Decode an address in compact form, 6 bytes for IPv4 or 18 bytes for IPv6.
+    pub fn from_compact(buf: &[u8]) -> MetaResult<Self> {
+        let ip = match buf.len() {
+            ADDRESS_V4_LEN => {
+                let octets: [u8; 4] = buf[..4].try_into().unwrap();
+                IpAddr::V4(Ipv4Addr::from(octets))
+            }
+            ADDRESS_V6_LEN => {
+                let octets: [u8; 16] = buf[..16].try_into().unwrap();
+                IpAddr::V6(Ipv6Addr::from(octets))
+            }
+            len => return Err(Error::CompactAddress(len)),
+        };
+        let port = u16::from_be_bytes([buf[buf.len() - 2], buf[buf.len() - 1]]);
+        Ok(PeerAddress(SocketAddr::new(ip, port)))
+    }
+}
+
+/// Decode a string of compact addresses, each `entry_len` bytes long.
+pub(crate) fn from_compact_list(buf: &[u8], entry_len: usize) -> Option<Vec<PeerAddress>> {
+    if entry_len != ADDRESS_V4_LEN && entry_len != ADDRESS_V6_LEN || buf.len() % entry_len != 0 {
+        return None;
+    }
+    buf.chunks(entry_len)
+        .map(|entry| PeerAddress::from_compact(entry).ok())
+        .collect()
+}
+
+/// Encode addresses into a string of compact addresses.
+pub(crate) fn to_compact_list<'a, I: IntoIterator<Item = &'a PeerAddress>>(addrs: I) -> Vec<u8> {
+    addrs
+        .into_iter()
+        .flat_map(PeerAddress::to_compact)
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -67,4 +117,35 @@ mod tests {
         let addr2: PeerAddress = from_str("l7:1.2.3.4i1234ee").unwrap();
         assert_eq!(addr1, addr2);
     }
+
+    #[test]
+    fn test_compact() {
+        let addr = PeerAddress("1.2.3.4:1234".parse().unwrap());
+        let buf = addr.to_compact();
+        assert_eq!(buf, vec![1, 2, 3, 4, 0x04, 0xd2]);
+        assert_eq!(PeerAddress::from_compact(&buf).unwrap(), addr);
+        assert!(PeerAddress::from_compact(&buf[1..]).is_err());
+
+        let addr = PeerAddress("[2001:db8::1]:6881".parse().unwrap());
+        let buf = addr.to_compact();
+        assert_eq!(buf.len(), ADDRESS_V6_LEN);
+        assert_eq!(PeerAddress::from_compact(&buf).unwrap(), addr);
+    }
+
+    #[test]
+    fn test_compact_list() {
+        let addrs = vec![
+            PeerAddress("1.2.3.4:1234".parse().unwrap()),
+            PeerAddress("5.6.7.8:6881".parse().unwrap()),
+        ];
+        let buf = to_compact_list(&addrs);
+        assert_eq!(buf, vec![1, 2, 3, 4, 0x04, 0xd2, 5, 6, 7, 8, 0x1a, 0xe1]);
+        assert_eq!(from_compact_list(&buf, ADDRESS_V4_LEN), Some(addrs));
+        assert_eq!(from_compact_list(&buf[1..], ADDRESS_V4_LEN), None);
+
+        let addrs = vec![PeerAddress("[2001:db8::1]:6881".parse().unwrap())];
+        let buf = to_compact_list(&addrs);
+        assert_eq!(buf.len(), ADDRESS_V6_LEN);
+        assert_eq!(from_compact_list(&buf, ADDRESS_V6_LEN), Some(addrs));
+    }
 }
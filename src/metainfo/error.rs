@@ -1,3 +1,5 @@
+use data_encoding::DecodeError;
+use hex::FromHexError;
 use std::net::AddrParseError;
 use std::path::StripPrefixError;
 use std::{io, result};
@@ -17,4 +19,20 @@ pub enum Error {
     PathConvert,
     #[error("Root path is empty")]
     EmptyRootPath,
+    #[error("Info has both length and files")]
+    AmbiguousLength,
+    #[error("Invalid compact address length {0}")]
+    CompactAddress(usize),
+    #[error("{0}")]
+    FromHex(#[from] FromHexError),
+    #[error("{0}")]
+    Base32(#[from] DecodeError),
+    #[error("Invalid hash length {0}")]
+    HashLength(usize),
+    #[error("Invalid piece length {0}")]
+    PieceLength(u64),
+    #[error("Parallelism must be at least 1")]
+    ZeroParallelism,
+    #[error("No file to hash")]
+    NoReaders,
 }
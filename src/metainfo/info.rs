@@ -1,10 +1,19 @@
 use super::error::{Error, Result};
 use super::piece::HashPieces;
+use async_std::io::{self, Read, ReadExt};
 use async_std::{fs, stream::StreamExt};
-use serde::{Deserialize, Serialize};
+use bitvec::prelude::{BitVec, Lsb0};
+use serde::{
+    de::{self, MapAccess, Visitor},
+    ser::SerializeMap,
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use serde_bencode::value::Value;
 use std::cmp::Ordering;
-use std::collections::VecDeque;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
 use std::path::{Path, PathBuf};
+use std::result::Result as StdResult;
 
 /// File represents a file in a torrent.
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Ord, Clone)]
@@ -23,10 +32,48 @@ impl PartialOrd for File {
     }
 }
 
+/// The default filter of the files collected into a torrent,
+/// it skips dotfiles and dot-directories, `.DS_Store` and `Thumbs.db`.
+pub fn default_file_filter(path: &Path) -> bool {
+    match path.file_name().and_then(|name| name.to_str()) {
+        Some(name) => !name.starts_with('.') && name != "Thumbs.db",
+        None => true,
+    }
+}
+
 impl File {
+    /// Collect the files under `root` sorted by their paths,
+    /// so the same directory always yields the same files in the same order.
+    /// If `root` is a file, it is the only file and its path is its file name.
+    /// Entries rejected by [`default_file_filter`] and symbolic links are skipped,
+    /// so the walk can neither loop nor leave `root`.
     pub async fn generate_from_root<P: AsRef<Path>>(root: P) -> Result<(Vec<File>, Vec<PathBuf>)> {
-        let mut files = Vec::new();
-        let mut paths = Vec::new();
+        Self::generate_from_root_with_filter(root, default_file_filter).await
+    }
+
+    /// Same as [`File::generate_from_root`], but only keeps the files and directories under `root`
+    /// for which `filter` returns true.
+    pub async fn generate_from_root_with_filter<P, F>(
+        root: P,
+        filter: F,
+    ) -> Result<(Vec<File>, Vec<PathBuf>)>
+    where
+        P: AsRef<Path>,
+        F: Fn(&Path) -> bool,
+    {
+        let meta_data = fs::metadata(root.as_ref()).await?;
+        if meta_data.is_file() {
+            let name = match root.as_ref().file_name() {
+                Some(s) => s.to_str().ok_or(Error::PathConvert)?.to_string(),
+                None => return Err(Error::EmptyRootPath),
+            };
+            let file = File {
+                length: meta_data.len(),
+                paths: vec![name],
+            };
+            return Ok((vec![file], vec![root.as_ref().into()]));
+        }
+        let mut entries = Vec::new();
         let mut deque: VecDeque<PathBuf> = VecDeque::new();
         deque.push_back(root.as_ref().into());
         while !deque.is_empty() {
@@ -35,25 +82,121 @@ impl File {
                 if let Ok(mut dir_entrys) = fs::read_dir(p).await {
                     while let Some(entry) = dir_entrys.next().await {
                         let entry = entry?;
-                        deque.push_front(entry.path().into())
+                        if entry.file_type().await?.is_symlink() {
+                            continue;
+                        }
+                        let path: PathBuf = entry.path().into();
+                        if filter(&path) {
+                            deque.push_front(path)
+                        }
                     }
                 }
             } else {
                 if let Ok(meta_data) = fs::metadata(&p).await {
                     assert!(meta_data.file_type().is_file());
                     let path = p.strip_prefix(root.as_ref())?;
-                    files.push(File {
+                    let file = File {
                         length: meta_data.len(),
                         paths: path
                             .iter()
                             .map(|p| p.to_owned().into_string().unwrap())
                             .collect(),
-                    });
-                    paths.push(p);
+                    };
+                    entries.push((file, p));
+                }
+            }
+        }
+        entries.sort_by(|(a, _), (b, _)| a.paths.cmp(&b.paths));
+        Ok(entries.into_iter().unzip())
+    }
+}
+
+/// V2File represents a file in the file tree of a v2 torrent.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct V2File {
+    /// Length of the file in bytes
+    pub length: u64,
+    /// Root of the SHA-256 merkle tree of the file's 16KiB blocks, absent for empty files
+    #[serde(rename = "pieces root")]
+    #[serde(with = "serde_bytes")]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[serde(default)]
+    pub pieces_root: Vec<u8>,
+}
+
+/// FileTreeNode represents a node of the file tree of a v2 torrent, see https://www.bittorrent.org/beps/bep_0052.html
+/// A file is a dictionary with a single empty key mapping to the file's properties.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum FileTreeNode {
+    File(V2File),
+    Directory(BTreeMap<String, FileTreeNode>),
+}
+
+impl FileTreeNode {
+    /// Collect the files under this node with their paths joined onto `path`.
+    fn collect_files<'a>(&'a self, path: PathBuf, files: &mut Vec<(PathBuf, &'a V2File)>) {
+        match self {
+            FileTreeNode::File(file) => files.push((path, file)),
+            FileTreeNode::Directory(children) => {
+                for (name, child) in children.iter() {
+                    child.collect_files(path.join(name), files);
+                }
+            }
+        }
+    }
+}
+
+impl Serialize for FileTreeNode {
+    fn serialize<S>(&self, serializer: S) -> StdResult<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            FileTreeNode::File(file) => {
+                let mut map = serializer.serialize_map(Some(1))?;
+                map.serialize_entry("", file)?;
+                map.end()
+            }
+            FileTreeNode::Directory(children) => children.serialize(serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for FileTreeNode {
+    fn deserialize<D>(deserializer: D) -> StdResult<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct FileTreeNodeVisitor;
+        impl<'de> Visitor<'de> for FileTreeNodeVisitor {
+            type Value = FileTreeNode;
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("`file tree` dictionary")
+            }
+
+            fn visit_map<A>(self, mut map: A) -> StdResult<Self::Value, A::Error>
+            where
+                A: MapAccess<'de>,
+            {
+                let mut file = None;
+                let mut children = BTreeMap::new();
+                while let Some(name) = map.next_key::<String>()? {
+                    if name.is_empty() {
+                        file = Some(map.next_value()?);
+                    } else {
+                        children.insert(name, map.next_value()?);
+                    }
+                }
+                match file {
+                    Some(file) if children.is_empty() => Ok(FileTreeNode::File(file)),
+                    Some(_) => Err(de::Error::custom(
+                        "file tree node is both file and directory",
+                    )),
+                    None => Ok(FileTreeNode::Directory(children)),
                 }
             }
         }
-        Ok((files, paths))
+        deserializer.deserialize_map(FileTreeNodeVisitor)
     }
 }
 
@@ -62,7 +205,7 @@ impl File {
 /// There are two possible forms:
 /// one for the case of a 'single-file' torrent with no directory structure,
 /// and one for the case of a 'multi-file' torrent (see https://wiki.theory.org/index.php/BitTorrentSpecification#Metainfo_File_Structure for details)
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Default, Clone)]
+#[derive(Debug, Deserialize, PartialEq, Eq, Default, Clone)]
 pub struct Info {
     /// Name of the file in the single file case
     /// Or the name of the directory in the muliple file case
@@ -74,6 +217,8 @@ pub struct Info {
     /// A string whose length is a multiple of 20
     /// It is to be subdivided into strings of length 20,
     /// Each of which is the SHA1 hash of the piece at the corresponding index
+    /// Absent in v2 only torrents
+    #[serde(default)]
     pub pieces: HashPieces,
     /// The length of the file in bytes in the single file case
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -83,62 +228,248 @@ pub struct Info {
     #[serde(skip_serializing_if = "Vec::is_empty")]
     #[serde(default)]
     pub files: Vec<File>,
+    /// Set to 1 if peers must only be obtained from the trackers, see bep_0027
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub private: Option<u8>,
+    /// The version of the metainfo format, 2 for v2 and hybrid torrents
+    #[serde(rename = "meta version")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub meta_version: Option<u64>,
+    /// The directory structure of a v2 torrent, its root is a directory
+    #[serde(rename = "file tree")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub file_tree: Option<FileTreeNode>,
+    /// Tag private trackers set so the info hash is unique to them
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub source: Option<String>,
+    /// Unknown keys, kept so that serializing doesn't change the info hash
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, Value>,
+}
+
+/// Info is serialized by hand, because whether `pieces` is written depends on the other fields:
+/// it is required by v1 and hybrid torrents even if empty, and absent in v2 only torrents.
+impl Serialize for Info {
+    fn serialize<S>(&self, serializer: S) -> StdResult<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("name", &self.name)?;
+        map.serialize_entry("piece length", &self.piece_length)?;
+        if !self.is_v2_only() {
+            map.serialize_entry("pieces", &self.pieces)?;
+        }
+        if let Some(length) = &self.length {
+            map.serialize_entry("length", length)?;
+        }
+        if !self.files.is_empty() {
+            map.serialize_entry("files", &self.files)?;
+        }
+        if let Some(private) = &self.private {
+            map.serialize_entry("private", private)?;
+        }
+        if let Some(meta_version) = &self.meta_version {
+            map.serialize_entry("meta version", meta_version)?;
+        }
+        if let Some(file_tree) = &self.file_tree {
+            map.serialize_entry("file tree", file_tree)?;
+        }
+        if let Some(source) = &self.source {
+            map.serialize_entry("source", source)?;
+        }
+        for (key, value) in self.extra.iter() {
+            map.serialize_entry(key, value)?;
+        }
+        map.end()
+    }
 }
 
 impl Info {
     pub async fn new<P: AsRef<Path>>(root_path: P, piece_length: u64) -> Result<Self> {
+        Self::new_with_filter(root_path, piece_length, default_file_filter).await
+    }
+
+    /// Same as [`Info::new`], but only the files kept by `filter` are part of the torrent,
+    /// see [`File::generate_from_root_with_filter`].
+    pub async fn new_with_filter<P, F>(root_path: P, piece_length: u64, filter: F) -> Result<Self>
+    where
+        P: AsRef<Path>,
+        F: Fn(&Path) -> bool,
+    {
         let name = match root_path.as_ref().file_name() {
             Some(s) => s
                 .to_str()
                 .map_or(Err(Error::PathConvert), |v| Ok(v.to_string()))?,
             None => return Err(Error::EmptyRootPath),
         };
-        let (files, paths) = File::generate_from_root(root_path).await?;
+        let is_dir = fs::metadata(root_path.as_ref()).await?.is_dir();
+        let (files, paths) = File::generate_from_root_with_filter(root_path, filter).await?;
         let mut readers = Vec::with_capacity(paths.len());
         for path in paths {
             readers.push(fs::OpenOptions::new().read(true).open(path).await?);
         }
         let pieces = HashPieces::hash_pieces(readers, piece_length).await?;
-        if files.len() == 1 {
+        if !is_dir {
             Ok(Self {
                 name,
                 piece_length,
                 pieces,
                 length: Some(files[0].length),
-                files: Vec::new(),
+                ..Default::default()
             })
         } else {
             Ok(Self {
                 name,
                 piece_length,
                 pieces,
-                length: None,
                 files,
+                ..Default::default()
             })
         }
     }
     pub fn is_multi(&self) -> bool {
         self.length.is_none()
     }
+
+    /// Is it a v2 torrent without the v1 description of the files?
+    fn is_v2_only(&self) -> bool {
+        self.meta_version == Some(2) && self.length.is_none() && self.files.is_empty()
+    }
+
+    /// Check that exactly one of the single file and the multi-file forms is used.
+    pub fn validate(&self) -> Result<()> {
+        if self.length.is_some() && !self.files.is_empty() {
+            return Err(Error::AmbiguousLength);
+        }
+        Ok(())
+    }
+
+    /// Hash the file(s) at `root`, the path given to [`Info::new`], and returns which pieces are valid.
+    /// Missing or short files are read as zeros, so only the pieces they cover are invalid.
+    pub async fn verify<P: AsRef<Path>>(&self, root: P) -> Result<BitVec<Lsb0, u8>> {
+        let files = match self.length {
+            Some(length) => vec![(root.as_ref().to_path_buf(), length)],
+            None => self
+                .files
+                .iter()
+                .map(|file| {
+                    (
+                        root.as_ref().join(file.paths.iter().collect::<PathBuf>()),
+                        file.length,
+                    )
+                })
+                .collect(),
+        };
+        let mut readers: Vec<Box<dyn Read + Unpin>> = Vec::with_capacity(files.len());
+        for (path, length) in files {
+            match fs::File::open(path).await {
+                Ok(file) => readers.push(Box::new(file.chain(io::repeat(0)).take(length))),
+                Err(_) => readers.push(Box::new(io::repeat(0).take(length))),
+            }
+        }
+        if readers.is_empty() {
+            return Ok(BitVec::repeat(false, self.pieces.0.len()));
+        }
+        let hashes = HashPieces::hash_pieces(readers, self.piece_length).await?;
+        Ok(self
+            .pieces
+            .0
+            .iter()
+            .enumerate()
+            .map(|(i, piece)| hashes.0.get(i) == Some(piece))
+            .collect())
+    }
+
+    /// Returns the number of bytes of all the files.
+    pub fn total_length(&self) -> u64 {
+        match self.length {
+            Some(length) => length,
+            None => self.files.iter().map(|file| file.length).sum(),
+        }
+    }
+
+    /// Returns the path and the length of every file, paths start with the name of the torrent.
+    pub fn files_with_paths(&self) -> Vec<(PathBuf, u64)> {
+        match self.length {
+            Some(length) => vec![(PathBuf::from(&self.name), length)],
+            None => self
+                .files
+                .iter()
+                .map(|file| {
+                    let mut path = PathBuf::from(&self.name);
+                    path.extend(&file.paths);
+                    (path, file.length)
+                })
+                .collect(),
+        }
+    }
+
+    /// Returns the path and the properties of every file in the v2 file tree,
+    /// paths start with the name of the torrent.
+    pub fn v2_files(&self) -> Vec<(PathBuf, &V2File)> {
+        let mut files = Vec::new();
+        if let Some(file_tree) = &self.file_tree {
+            file_tree.collect_files(PathBuf::from(&self.name), &mut files);
+        }
+        files
+    }
+
+    /// Returns the `(file path, file offset, length)` segments covered by the block at `begin` in the piece,
+    /// paths are the ones of [`Info::files_with_paths`]. The part of the block past the last file is dropped.
+    pub fn locate(
+        &self,
+        piece_index: usize,
+        begin: usize,
+        length: usize,
+    ) -> Vec<(PathBuf, u64, u64)> {
+        let mut start = piece_index as u64 * self.piece_length + begin as u64;
+        let end = start + length as u64;
+        let mut segments = Vec::new();
+        let mut file_start = 0;
+        for (path, file_length) in self.files_with_paths() {
+            if start >= end {
+                break;
+            }
+            let file_end = file_start + file_length;
+            if start < file_end {
+                let segment_end = end.min(file_end);
+                segments.push((path, start - file_start, segment_end - start));
+                start = segment_end;
+            }
+            file_start = file_end;
+        }
+        segments
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::metainfo::piece::PIECE_SIZE_256_KB;
+    use crate::metainfo::piece::{HashPiece, PIECE_SIZE_256_KB};
     use async_std::task::block_on;
     use serde_bencode::{de::from_bytes, ser::to_bytes};
     use std::io::Write;
-    use tempfile::{tempdir, NamedTempFile};
+    use tempfile::{tempdir, Builder};
 
     #[test]
     fn test_info() {
         let dir = tempdir().unwrap();
         dbg!(dir.path());
-        let mut tmpfile1 = NamedTempFile::new_in(dir.path()).unwrap();
+        let mut tmpfile1 = Builder::new()
+            .prefix("tmp")
+            .tempfile_in(dir.path())
+            .unwrap();
         write!(tmpfile1, "Hello World!1").unwrap();
 
-        let mut tmpfile2 = NamedTempFile::new_in(dir.path()).unwrap();
+        let mut tmpfile2 = Builder::new()
+            .prefix("tmp")
+            .tempfile_in(dir.path())
+            .unwrap();
         write!(tmpfile2, "Hello World!2").unwrap();
 
         block_on(async {
@@ -154,4 +485,244 @@ mod tests {
             assert_eq!(info, info1);
         });
     }
+
+    #[test]
+    fn test_info_single_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("a.iso");
+        std::fs::write(&path, "Hello World!").unwrap();
+        block_on(async {
+            let (files, paths) = File::generate_from_root(&path).await.unwrap();
+            assert_eq!(files[0].paths, vec!["a.iso"]);
+            assert_eq!(paths, vec![path.clone()]);
+
+            let info = Info::new(&path, PIECE_SIZE_256_KB).await.unwrap();
+            assert_eq!(info.name, "a.iso");
+            assert_eq!(info.length, Some(12));
+            assert!(info.files.is_empty());
+            assert!(!info.is_multi());
+
+            // A directory holding a single file is still a multi-file torrent
+            let info = Info::new(dir.path(), PIECE_SIZE_256_KB).await.unwrap();
+            assert!(info.is_multi());
+            assert_eq!(info.files[0].paths, vec!["a.iso"]);
+            assert_eq!(
+                info.pieces,
+                Info::new(&path, PIECE_SIZE_256_KB).await.unwrap().pieces
+            );
+        });
+    }
+
+    #[test]
+    fn test_info_filter() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().join("root");
+        std::fs::create_dir_all(root.join(".git")).unwrap();
+        std::fs::create_dir_all(root.join("c")).unwrap();
+        for name in &[".DS_Store", ".git/HEAD", "a.txt", "c/Thumbs.db", "c/b.log"] {
+            std::fs::write(root.join(name), name).unwrap();
+        }
+        block_on(async {
+            let info = Info::new(&root, PIECE_SIZE_256_KB).await.unwrap();
+            let paths: Vec<_> = info.files.iter().map(|file| file.paths.join("/")).collect();
+            assert_eq!(paths, vec!["a.txt", "c/b.log"]);
+            let expected = HashPieces::hash_pieces(vec![&b"a.txt"[..], &b"c/b.log"[..]], 16)
+                .await
+                .unwrap();
+            assert_eq!(Info::new(&root, 16).await.unwrap().pieces, expected);
+
+            let info = Info::new_with_filter(
+                &root,
+                PIECE_SIZE_256_KB,
+                |path| !matches!(path.extension(), Some(ext) if ext == "log"),
+            )
+            .await
+            .unwrap();
+            let paths: Vec<_> = info.files.iter().map(|file| file.paths.join("/")).collect();
+            assert_eq!(
+                paths,
+                vec![".DS_Store", ".git/HEAD", "a.txt", "c/Thumbs.db"]
+            );
+        });
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_info_symlinks() {
+        use std::os::unix::fs::symlink;
+        let dir = tempdir().unwrap();
+        let outside = dir.path().join("outside.txt");
+        std::fs::write(&outside, "outside").unwrap();
+        let root = dir.path().join("root");
+        std::fs::create_dir_all(root.join("c")).unwrap();
+        std::fs::write(root.join("a.txt"), "a").unwrap();
+        symlink(&root, root.join("c").join("loop")).unwrap();
+        symlink(&outside, root.join("outside.txt")).unwrap();
+        symlink(root.join("a.txt"), root.join("b.txt")).unwrap();
+        let (files, paths) = block_on(File::generate_from_root(&root)).unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].paths, vec!["a.txt"]);
+        assert_eq!(paths, vec![root.join("a.txt")]);
+    }
+
+    #[test]
+    fn test_info_empty_pieces() {
+        // a v1 torrent of empty content still needs its pieces
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("empty.txt");
+        std::fs::write(&path, "").unwrap();
+        let info = block_on(Info::new(&path, PIECE_SIZE_256_KB)).unwrap();
+        assert!(info.pieces.is_empty());
+        assert_eq!(
+            to_bytes(&info).unwrap(),
+            b"d6:lengthi0e4:name9:empty.txt12:piece lengthi262144e6:pieces0:e"
+        );
+        assert_eq!(from_bytes::<Info>(&to_bytes(&info).unwrap()).unwrap(), info);
+
+        let v2_only = Info {
+            name: "a.txt".to_string(),
+            piece_length: PIECE_SIZE_256_KB,
+            meta_version: Some(2),
+            file_tree: Some(FileTreeNode::Directory(BTreeMap::new())),
+            ..Default::default()
+        };
+        let encoded = to_bytes(&v2_only).unwrap();
+        assert_eq!(
+            encoded,
+            b"d9:file treede12:meta versioni2e4:name5:a.txt12:piece lengthi262144ee"
+        );
+        assert_eq!(from_bytes::<Info>(&encoded).unwrap(), v2_only);
+    }
+
+    #[test]
+    fn test_info_deterministic() {
+        let names = ["b.txt", "a.txt", "c/d.txt", "c/a.txt"];
+        let mut info_hashes = Vec::new();
+        for names in [names, [names[2], names[0], names[3], names[1]]] {
+            let dir = tempdir().unwrap();
+            let root = dir.path().join("root");
+            std::fs::create_dir_all(root.join("c")).unwrap();
+            for name in names.iter() {
+                std::fs::write(root.join(name), name).unwrap();
+            }
+            let info = block_on(Info::new(&root, PIECE_SIZE_256_KB)).unwrap();
+            let paths: Vec<_> = info.files.iter().map(|file| file.paths.join("/")).collect();
+            assert_eq!(paths, vec!["a.txt", "b.txt", "c/a.txt", "c/d.txt"]);
+            info_hashes.push(HashPiece::from(&info));
+        }
+        assert_eq!(info_hashes[0], info_hashes[1]);
+    }
+
+    #[test]
+    fn test_info_verify() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("a.txt");
+        std::fs::write(&path, [1u8; 40]).unwrap();
+        block_on(async {
+            let info = Info::new(&path, 16).await.unwrap();
+            assert_eq!(info.pieces.0.len(), 3);
+            assert!(info.verify(&path).await.unwrap().all());
+            let mut content = [1u8; 40];
+            content[20] = 2;
+            std::fs::write(&path, content).unwrap();
+            let valid = info.verify(&path).await.unwrap();
+            assert_eq!(
+                valid.iter().by_val().collect::<Vec<_>>(),
+                [true, false, true]
+            );
+        });
+
+        // piece 0 spans a.txt and the start of b/c.txt
+        let root = dir.path().join("root");
+        std::fs::create_dir_all(root.join("b")).unwrap();
+        std::fs::write(root.join("a.txt"), [1u8; 10]).unwrap();
+        std::fs::write(root.join("b").join("c.txt"), [2u8; 30]).unwrap();
+        block_on(async {
+            let info = Info::new(&root, 16).await.unwrap();
+            assert!(info.verify(&root).await.unwrap().all());
+            let mut content = [2u8; 30];
+            content[0] = 3;
+            std::fs::write(root.join("b").join("c.txt"), content).unwrap();
+            let valid = info.verify(&root).await.unwrap();
+            assert_eq!(
+                valid.iter().by_val().collect::<Vec<_>>(),
+                [false, true, true]
+            );
+            std::fs::remove_file(root.join("a.txt")).unwrap();
+            let valid = info.verify(&root).await.unwrap();
+            assert_eq!(
+                valid.iter().by_val().collect::<Vec<_>>(),
+                [false, true, true]
+            );
+        });
+    }
+
+    #[test]
+    fn test_info_locate() {
+        let info = Info {
+            name: "dir".to_string(),
+            piece_length: 16,
+            files: vec![
+                File {
+                    length: 10,
+                    paths: vec!["a.txt".to_string()],
+                },
+                File {
+                    length: 0,
+                    paths: vec!["empty".to_string()],
+                },
+                File {
+                    length: 30,
+                    paths: vec!["b".to_string(), "c.txt".to_string()],
+                },
+            ],
+            ..Default::default()
+        };
+        let a = Path::new("dir").join("a.txt");
+        let c = Path::new("dir").join("b").join("c.txt");
+        assert_eq!(
+            info.locate(0, 0, 16),
+            vec![(a.clone(), 0, 10), (c.clone(), 0, 6)]
+        );
+        assert_eq!(info.locate(0, 4, 4), vec![(a, 4, 4)]);
+        assert_eq!(info.locate(1, 4, 8), vec![(c.clone(), 10, 8)]);
+        assert_eq!(info.locate(2, 0, 16), vec![(c, 22, 8)]);
+        assert!(info.locate(3, 0, 16).is_empty());
+    }
+
+    #[test]
+    fn test_info_files() {
+        let mut info = Info {
+            name: "a.txt".to_string(),
+            piece_length: PIECE_SIZE_256_KB,
+            length: Some(6),
+            ..Default::default()
+        };
+        assert!(info.validate().is_ok());
+        assert_eq!(info.total_length(), 6);
+        assert_eq!(info.files_with_paths(), vec![(PathBuf::from("a.txt"), 6)]);
+
+        info.name = "dir".to_string();
+        info.files = vec![
+            File {
+                length: 3,
+                paths: vec!["a.txt".to_string()],
+            },
+            File {
+                length: 4,
+                paths: vec!["sub".to_string(), "b.txt".to_string()],
+            },
+        ];
+        assert!(matches!(info.validate(), Err(Error::AmbiguousLength)));
+        info.length = None;
+        assert!(info.validate().is_ok());
+        assert_eq!(info.total_length(), 7);
+        assert_eq!(
+            info.files_with_paths(),
+            vec![
+                (Path::new("dir").join("a.txt"), 3),
+                (Path::new("dir").join("sub").join("b.txt"), 4)
+            ]
+        );
+    }
 }
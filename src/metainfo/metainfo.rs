@@ -1,11 +1,21 @@
 use super::info::Info;
 use super::{address::PeerAddress, HashPiece};
 use crate::error::Result;
+use async_std::{
+    fs,
+    path::{Path, PathBuf},
+};
 use serde::{
     de::{self, SeqAccess, Visitor},
     Deserialize, Deserializer, Serialize, Serializer,
 };
+use serde_bencode::value::Value;
+use serde_bytes::ByteBuf;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::ops::Range;
 use std::result::Result as StdResult;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use std::{collections::HashSet, fmt, str};
 use url::Url;
 
@@ -14,6 +24,12 @@ use url::Url;
 #[derive(Debug, PartialEq, Eq)]
 pub struct UrlList(Vec<Url>);
 
+impl UrlList {
+    pub fn urls(&self) -> &[Url] {
+        &self.0
+    }
+}
+
 impl Serialize for UrlList {
     fn serialize<S>(&self, serializer: S) -> StdResult<S::Ok, S::Error>
     where
@@ -43,15 +59,14 @@ impl<'de> Deserialize<'de> for UrlList {
             where
                 E: de::Error,
             {
-                Ok(UrlList(vec![
-                    Url::parse(str::from_utf8(v).unwrap()).unwrap()
-                ]))
+                self.visit_str(str::from_utf8(v).map_err(E::custom)?)
             }
             fn visit_str<E>(self, v: &str) -> StdResult<Self::Value, E>
             where
                 E: de::Error,
             {
-                Ok(UrlList(vec![Url::parse(v).unwrap()]))
+                let url = Url::parse(v).map_err(E::custom)?;
+                Ok(UrlList(vec![url]))
             }
 
             fn visit_seq<A>(self, mut seq: A) -> StdResult<Self::Value, A::Error>
@@ -60,7 +75,7 @@ impl<'de> Deserialize<'de> for UrlList {
             {
                 let mut v = Vec::new();
                 while let Some(elem) = seq.next_element::<String>()? {
-                    v.push(Url::parse(&elem).unwrap());
+                    v.push(Url::parse(&elem).map_err(de::Error::custom)?);
                 }
                 Ok(UrlList(v))
             }
@@ -69,7 +84,7 @@ impl<'de> Deserialize<'de> for UrlList {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize)]
 /// The MetaInfo represents the .torrent file.
 pub struct MetaInfo {
     /// Info dictionary
@@ -117,45 +132,291 @@ pub struct MetaInfo {
     #[serde(skip_serializing_if = "String::is_empty")]
     #[serde(default)]
     pub encoding: String,
+
+    /// The merkle tree layers of the files larger than a piece keyed by their pieces root, see bep_0052
+    #[serde(rename = "piece layers")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub piece_layers: Option<BTreeMap<ByteBuf, ByteBuf>>,
+
+    /// Unknown keys, kept so that serializing round-trips
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, Value>,
+
+    /// The original bytes of the info dictionary, set by [`MetaInfo::from_bytes`] and [`MetaInfo::from_file`].
+    /// They are only used while they still decode to `info`, so changing `info` never writes or hashes stale bytes
+    #[serde(skip)]
+    pub raw_info: Option<Vec<u8>>,
 }
 
+/// The original bytes of the info dictionary don't take part in the comparison,
+/// they are only an encoding of `info`.
+impl PartialEq for MetaInfo {
+    fn eq(&self, other: &Self) -> bool {
+        self.info == other.info
+            && self.announce == other.announce
+            && self.announce_list == other.announce_list
+            && self.nodes == other.nodes
+            && self.url_list == other.url_list
+            && self.creation_date == other.creation_date
+            && self.comment == other.comment
+            && self.created_by == other.created_by
+            && self.encoding == other.encoding
+            && self.piece_layers == other.piece_layers
+            && self.extra == other.extra
+    }
+}
+
+impl Eq for MetaInfo {}
+
 impl MetaInfo {
+    /// Parse a .torrent file, keeping the original bytes of the info dictionary.
+    /// The info dictionary must be valid, see [`Info::validate`].
+    pub fn from_bytes(buf: &[u8]) -> Result<Self> {
+        let mut meta_info: MetaInfo = serde_bencode::from_bytes(buf)?;
+        meta_info.info.validate()?;
+        meta_info.raw_info = info_range(buf).map(|range| buf[range].to_vec());
+        Ok(meta_info)
+    }
+
+    /// Encode the .torrent file, the info dictionary is written as it was read if its bytes are known.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut buf = serde_bencode::to_bytes(self)?;
+        if let (Some(raw_info), Some(range)) = (self.raw_info(), info_range(&buf)) {
+            buf.splice(range, raw_info.iter().copied());
+        }
+        Ok(buf)
+    }
+
+    /// Read and parse a .torrent file.
+    pub async fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::from_bytes(&fs::read(path).await?)
+    }
+
+    /// Encode and write a .torrent file.
+    pub async fn to_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        fs::write(path, self.to_bytes()?).await?;
+        Ok(())
+    }
+
+    /// Returns the SHA1 of the info dictionary as it appeared in the .torrent file.
+    /// Re-encoding `info` drops unknown keys and non-canonical encodings, which changes the hash,
+    /// so it is only the fallback when the original bytes are unknown.
+    pub fn info_hash(&self) -> HashPiece {
+        match self.raw_info() {
+            Some(raw_info) => HashPiece::from(raw_info),
+            None => self.get_info_hash(),
+        }
+    }
+
+    /// Returns the SHA-256 of the info dictionary for a v2 or hybrid torrent.
+    pub fn info_hash_v2(&self) -> Result<Option<[u8; 32]>> {
+        if !self.is_v2() {
+            return Ok(None);
+        }
+        let hash = match self.raw_info() {
+            Some(raw_info) => Sha256::digest(raw_info),
+            None => Sha256::digest(&serde_bencode::to_bytes(&self.info)?),
+        };
+        Ok(Some(hash.into()))
+    }
+
+    /// Returns the original bytes of the info dictionary if they still decode to `info`.
+    fn raw_info(&self) -> Option<&[u8]> {
+        let raw_info = self.raw_info.as_deref()?;
+        match serde_bencode::from_bytes::<Info>(raw_info) {
+            Ok(info) if info == self.info => Some(raw_info),
+            _ => None,
+        }
+    }
+
+    /// Is it a v2 torrent, see https://www.bittorrent.org/beps/bep_0052.html
+    pub fn is_v2(&self) -> bool {
+        self.info.meta_version == Some(2) && self.info.file_tree.is_some()
+    }
+
+    /// Is it a v2 torrent that is also usable as a v1 torrent?
+    pub fn is_hybrid(&self) -> bool {
+        self.is_v2() && !self.info.pieces.is_empty()
+    }
+
+    /// Returns the creation time of the torrent.
+    pub fn created_at(&self) -> Option<SystemTime> {
+        self.creation_date
+            .map(|secs| UNIX_EPOCH + Duration::from_secs(secs))
+    }
+
+    /// Set the creation time of the torrent, it is stored in seconds since the UNIX epoch.
+    pub fn set_created_at(&mut self, time: SystemTime) {
+        self.creation_date = time
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .ok();
+    }
+
     pub fn get_name(&self) -> String {
         self.info.name.clone()
     }
     pub fn get_info_hash(&self) -> HashPiece {
         (&self.info).into()
     }
+
+    /// Returns the trackers of all tiers in the order they should be tried.
     pub fn get_trackers(&self) -> Result<Vec<Url>> {
-        if let Some(announce) = &self.announce {
-            return Ok(vec![Url::parse(announce)?]);
+        Ok(self.tracker_tiers()?.into_iter().flatten().collect())
+    }
+
+    /// Returns the tiers of trackers defined in https://www.bittorrent.org/beps/bep_0012.html,
+    /// `announce` is only used without `announce-list`. A tracker is kept in its first tier only.
+    pub fn tracker_tiers(&self) -> Result<Vec<Vec<Url>>> {
+        if self.announce_list.is_empty() {
+            return match &self.announce {
+                Some(announce) => Ok(vec![vec![Url::parse(announce)?]]),
+                None => Ok(Vec::new()),
+            };
         }
         let mut seen = HashSet::new();
-        let urls = self
-            .announce_list
-            .iter()
-            .flatten()
-            .flat_map(|s| {
-                if seen.contains(s) {
-                    None
-                } else {
-                    let url = Url::parse(s).unwrap();
-                    seen.insert(s);
-                    Some(url)
+        let mut tiers = Vec::new();
+        for tier in &self.announce_list {
+            let mut urls = Vec::new();
+            for s in tier {
+                if seen.insert(s) {
+                    urls.push(Url::parse(s)?);
                 }
-            })
+            }
+            if !urls.is_empty() {
+                tiers.push(urls);
+            }
+        }
+        Ok(tiers)
+    }
+}
+
+/// MetaInfoBuilder creates a .torrent file from the file or directory at a root path.
+#[derive(Debug)]
+pub struct MetaInfoBuilder {
+    root: PathBuf,
+    piece_length: u64,
+    announce: Option<String>,
+    announce_list: Vec<Vec<String>>,
+    comment: String,
+    web_seeds: Vec<Url>,
+    private: bool,
+}
+
+impl MetaInfoBuilder {
+    pub fn new<P: AsRef<Path>>(root: P, piece_length: u64) -> Self {
+        Self {
+            root: root.as_ref().to_path_buf(),
+            piece_length,
+            announce: None,
+            announce_list: Vec::new(),
+            comment: String::new(),
+            web_seeds: Vec::new(),
+            private: false,
+        }
+    }
+
+    pub fn announce(mut self, url: &Url) -> Self {
+        self.announce = Some(url.to_string());
+        self
+    }
+
+    /// Set the tiers of trackers, see bep_0012
+    pub fn announce_list(mut self, tiers: Vec<Vec<Url>>) -> Self {
+        self.announce_list = tiers
+            .iter()
+            .map(|tier| tier.iter().map(|url| url.to_string()).collect())
             .collect();
-        Ok(urls)
+        self
+    }
+
+    pub fn comment<S: Into<String>>(mut self, comment: S) -> Self {
+        self.comment = comment.into();
+        self
+    }
+
+    /// Add a web address where the torrent data can be retrieved, see bep_0019
+    pub fn web_seed(mut self, url: Url) -> Self {
+        self.web_seeds.push(url);
+        self
+    }
+
+    pub fn private(mut self, private: bool) -> Self {
+        self.private = private;
+        self
+    }
+
+    /// Hash the files and create the MetaInfo, the creation date is now.
+    pub async fn build(self) -> super::error::Result<MetaInfo> {
+        let mut info = Info::new(&self.root, self.piece_length).await?;
+        if self.private {
+            info.private = Some(1);
+        }
+        let mut meta_info = MetaInfo {
+            info,
+            announce: self.announce,
+            announce_list: self.announce_list,
+            nodes: Vec::new(),
+            url_list: Some(UrlList(self.web_seeds)).filter(|url_list| !url_list.0.is_empty()),
+            creation_date: None,
+            comment: self.comment,
+            created_by: String::new(),
+            encoding: String::new(),
+            piece_layers: None,
+            extra: BTreeMap::new(),
+            raw_info: None,
+        };
+        meta_info.set_created_at(SystemTime::now());
+        Ok(meta_info)
+    }
+}
+
+/// Returns the end of the bencoded value starting at `pos`.
+fn skip_value(buf: &[u8], pos: usize) -> Option<usize> {
+    match buf.get(pos)? {
+        b'i' => Some(pos + buf[pos..].iter().position(|byte| *byte == b'e')? + 1),
+        b'l' | b'd' => {
+            let mut pos = pos + 1;
+            while *buf.get(pos)? != b'e' {
+                pos = skip_value(buf, pos)?;
+            }
+            Some(pos + 1)
+        }
+        _ => {
+            let colon = pos + buf[pos..].iter().position(|byte| *byte == b':')?;
+            let len: usize = str::from_utf8(&buf[pos..colon]).ok()?.parse().ok()?;
+            (colon + 1).checked_add(len).filter(|end| *end <= buf.len())
+        }
+    }
+}
+
+/// Returns the range of the info dictionary in a bencoded .torrent file.
+fn info_range(buf: &[u8]) -> Option<Range<usize>> {
+    if buf.first() != Some(&b'd') {
+        return None;
     }
+    let mut pos = 1;
+    while *buf.get(pos)? != b'e' {
+        let key_end = skip_value(buf, pos)?;
+        let value_end = skip_value(buf, key_end)?;
+        if &buf[pos..key_end] == b"4:info" {
+            return Some(key_end..value_end);
+        }
+        pos = value_end;
+    }
+    None
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use async_std::task::block_on;
     use serde_bencode::{
         de::{from_bytes, from_str},
         ser::{to_bytes, to_string},
     };
+    use tempfile::tempdir;
 
     #[test]
     fn test_url_list() {
@@ -183,6 +444,106 @@ mod tests {
         )
     }
 
+    #[test]
+    fn test_invalid_urls() {
+        assert!(from_str::<UrlList>("9:not a url").is_err());
+        assert!(from_str::<UrlList>("l15:http://qq1.com/9:not a urle").is_err());
+        assert!(from_bytes::<UrlList>(b"2:\xff\xfe").is_err());
+
+        let raw_torrent = b"d13:announce-listll15:http://qq1.com/el9:not a urlee\
+            4:infod6:lengthi6e4:name5:1.txt12:piece lengthi524288e6:pieces0:ee";
+        let meta_info = MetaInfo::from_bytes(raw_torrent).unwrap();
+        assert!(meta_info.get_trackers().is_err());
+        let raw_torrent = b"d4:infod6:lengthi6e4:name5:1.txt12:piece lengthi524288e\
+            6:pieces0:e8:url-list9:not a urle";
+        assert!(MetaInfo::from_bytes(raw_torrent).is_err());
+    }
+
+    #[test]
+    fn test_extra_keys() {
+        let raw_torrent = b"d8:announce15:http://qq1.com/\
+            4:infod6:lengthi6e4:name5:1.txt12:piece lengthi524288e6:pieces20:aaaaaaaaaaaaaaaaaaaa\
+            6:source3:abc12:x-cross-seedd1:ai1eee7:x-extrali1ei2eee";
+        let meta_info: MetaInfo = from_bytes(raw_torrent).unwrap();
+        assert_eq!(meta_info.info.source.as_deref(), Some("abc"));
+        assert!(meta_info.info.extra.contains_key("x-cross-seed"));
+        assert!(meta_info.extra.contains_key("x-extra"));
+        assert_eq!(to_bytes(&meta_info).unwrap(), raw_torrent.to_vec());
+        assert_eq!(
+            to_bytes(&meta_info.info).unwrap(),
+            raw_torrent[info_range(raw_torrent).unwrap()].to_vec()
+        );
+    }
+
+    #[test]
+    fn test_tracker_tiers() {
+        let raw_torrent = b"d8:announce15:http://qq0.com/\
+            13:announce-listll15:http://qq1.com/15:http://qq2.com/el15:http://qq3.com/\
+            15:http://qq1.com/el15:http://qq2.com/ee\
+            4:infod6:lengthi6e4:name5:1.txt12:piece lengthi524288e6:pieces0:ee";
+        let meta_info = MetaInfo::from_bytes(raw_torrent).unwrap();
+        let url = |i| Url::parse(&format!("http://qq{}.com/", i)).unwrap();
+        assert_eq!(
+            meta_info.tracker_tiers().unwrap(),
+            vec![vec![url(1), url(2)], vec![url(3)]]
+        );
+        assert_eq!(
+            meta_info.get_trackers().unwrap(),
+            vec![url(1), url(2), url(3)]
+        );
+
+        let raw_torrent = b"d8:announce15:http://qq0.com/\
+            4:infod6:lengthi6e4:name5:1.txt12:piece lengthi524288e6:pieces0:ee";
+        let meta_info = MetaInfo::from_bytes(raw_torrent).unwrap();
+        assert_eq!(meta_info.tracker_tiers().unwrap(), vec![vec![url(0)]]);
+    }
+
+    #[test]
+    fn test_out_of_range_integers() {
+        for raw_torrent in [
+            &b"d4:infod6:lengthi99999999999999999999e4:name5:1.txt12:piece lengthi524288e\
+                6:pieces0:ee"[..],
+            &b"d4:infod6:lengthi-1e4:name5:1.txt12:piece lengthi524288e6:pieces0:ee"[..],
+            &b"d13:creation datei99999999999999999999e\
+                4:infod6:lengthi6e4:name5:1.txt12:piece lengthi524288e6:pieces0:ee"[..],
+        ] {
+            assert!(matches!(
+                MetaInfo::from_bytes(raw_torrent),
+                Err(crate::error::Error::BencodeErr(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_oversized_length() {
+        // the announced lengths are far beyond the input and must not be allocated upfront
+        for raw_torrent in [
+            &b"d4:info2147483648:abce"[..],
+            &b"d7:comment2147483648:abc4:infod6:lengthi6e4:name5:1.txt12:piece lengthi524288e\
+                6:pieces0:ee"[..],
+            &b"d4:info18446744073709551615:abce"[..],
+        ] {
+            assert!(MetaInfo::from_bytes(raw_torrent).is_err());
+        }
+        assert_eq!(info_range(b"d4:info18446744073709551615:abce"), None);
+    }
+
+    #[test]
+    fn test_ambiguous_length() {
+        let raw_torrent = b"d4:infod5:filesld6:lengthi6e4:pathl5:1.txteee\
+            6:lengthi6e4:name3:dir12:piece lengthi524288e6:pieces0:ee";
+        assert!(matches!(
+            MetaInfo::from_bytes(raw_torrent),
+            Err(crate::error::Error::MetaInfoErr(
+                crate::metainfo::Error::AmbiguousLength
+            ))
+        ));
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("ambiguous.torrent");
+        std::fs::write(&path, &raw_torrent[..]).unwrap();
+        assert!(block_on(MetaInfo::from_file(&path)).is_err());
+    }
+
     #[test]
     fn test_meta_info() {
         let raw_torrent =
@@ -195,4 +556,161 @@ mod tests {
         assert_eq!(&meta_info_a, &meta_info_b);
         assert_eq!(to_bytes(&meta_info_b).unwrap().as_slice(), &raw_torrent[..]);
     }
+
+    #[test]
+    fn test_info_hash() {
+        for (raw_torrent, info_hash) in [
+            (
+                &include_bytes!("example/archlinux-2011.08.19-netinstall-i686.iso.torrent")[..],
+                "500f29c0c537f5e41c6af676b7633de9d080d237",
+            ),
+            (
+                &include_bytes!("../magnet/example/debian-11.0.0-amd64-netinst.iso.torrent")[..],
+                "3b4bd6f8296403dfebd41062f4658f5b61d2bc26",
+            ),
+        ] {
+            let meta_info = MetaInfo::from_bytes(raw_torrent).unwrap();
+            assert_eq!(hex::encode(meta_info.info_hash()), info_hash);
+        }
+
+        // the keys are not sorted, re-encoding would reorder them
+        let raw_torrent = b"d4:infod4:name5:1.txt6:lengthi6e12:piece lengthi524288e\
+            6:pieces20:aaaaaaaaaaaaaaaaaaaaee";
+        let meta_info = MetaInfo::from_bytes(raw_torrent).unwrap();
+        let raw_info = &raw_torrent[7..raw_torrent.len() - 1];
+        assert_eq!(meta_info.info_hash(), HashPiece::from(raw_info));
+        assert_ne!(meta_info.info_hash(), meta_info.get_info_hash());
+        assert_eq!(meta_info.to_bytes().unwrap().as_slice(), &raw_torrent[..]);
+
+        let decoded: MetaInfo = serde_bencode::from_bytes(raw_torrent).unwrap();
+        assert!(decoded.raw_info.is_none());
+        assert_eq!(decoded, meta_info);
+
+        // once info is changed the original bytes are stale and not used anymore
+        let mut meta_info = meta_info;
+        meta_info.info.name = "2.txt".to_string();
+        assert_eq!(meta_info.info_hash(), meta_info.get_info_hash());
+        let encoded = meta_info.to_bytes().unwrap();
+        assert_eq!(encoded, serde_bencode::to_bytes(&meta_info).unwrap());
+        let decoded = MetaInfo::from_bytes(&encoded).unwrap();
+        assert_eq!(decoded.info.name, "2.txt");
+        assert_eq!(decoded.info_hash(), meta_info.info_hash());
+    }
+
+    #[test]
+    fn test_meta_info_builder() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().join("dir");
+        std::fs::create_dir(&root).unwrap();
+        std::fs::write(root.join("a.txt"), "Hello World!1").unwrap();
+        std::fs::write(root.join("b.txt"), "Hello World!2").unwrap();
+        let tracker = Url::parse("http://tracker.example.com/announce").unwrap();
+        let backup = Url::parse("udp://backup.example.com:6969").unwrap();
+        let web_seed = Url::parse("http://seed.example.com/").unwrap();
+        let meta_info = block_on(
+            MetaInfoBuilder::new(&root, 16)
+                .announce(&tracker)
+                .announce_list(vec![vec![tracker.clone()], vec![backup.clone()]])
+                .comment("test")
+                .web_seed(web_seed.clone())
+                .private(true)
+                .build(),
+        )
+        .unwrap();
+        assert!(meta_info.creation_date.is_some());
+        assert_eq!(meta_info.info.private, Some(1));
+        assert_eq!(meta_info.info.files.len(), 2);
+
+        let parsed = MetaInfo::from_bytes(&meta_info.to_bytes().unwrap()).unwrap();
+        assert_eq!(parsed.info, meta_info.info);
+        assert_eq!(parsed.info_hash(), meta_info.info_hash());
+        assert_eq!(parsed.announce.as_deref(), Some(tracker.as_str()));
+        assert_eq!(
+            parsed.announce_list,
+            vec![vec![tracker.to_string()], vec![backup.to_string()]]
+        );
+        assert_eq!(parsed.comment, "test");
+        assert_eq!(parsed.url_list, Some(UrlList(vec![web_seed])));
+        assert_eq!(parsed.creation_date, meta_info.creation_date);
+    }
+
+    #[test]
+    fn test_created_at() {
+        let raw_torrent =
+            include_bytes!("example/archlinux-2011.08.19-netinstall-i686.iso.torrent");
+        let mut meta_info = MetaInfo::from_bytes(raw_torrent).unwrap();
+        let now = SystemTime::now();
+        meta_info.set_created_at(now);
+        let created_at = meta_info.created_at().unwrap();
+        assert!(created_at <= now);
+        assert!(now.duration_since(created_at).unwrap() < Duration::from_secs(1));
+        meta_info.set_created_at(created_at);
+        assert_eq!(meta_info.created_at(), Some(created_at));
+        assert_eq!(
+            meta_info.creation_date,
+            Some(now.duration_since(UNIX_EPOCH).unwrap().as_secs())
+        );
+    }
+
+    #[test]
+    fn test_meta_info_v2() {
+        let raw_torrent = include_bytes!("example/v2.torrent");
+        let meta_info = MetaInfo::from_bytes(raw_torrent).unwrap();
+        assert!(meta_info.is_v2());
+        assert!(!meta_info.is_hybrid());
+        assert_eq!(
+            hex::encode(meta_info.info_hash_v2().unwrap().unwrap()),
+            "eb0b34011ad11991170e623d8e8e63f93788bddbb2d0e853fc6fc4821fdcae5e"
+        );
+        let files = meta_info.info.v2_files();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].0, std::path::Path::new("v2test").join("a.txt"));
+        assert_eq!(files[0].1.length, 40000);
+        assert_eq!(
+            files[1].0,
+            std::path::Path::new("v2test").join("b").join("c.txt")
+        );
+        let piece_layers = meta_info.piece_layers.as_ref().unwrap();
+        assert_eq!(piece_layers.len(), 1);
+        let layer = &piece_layers[&ByteBuf::from(files[0].1.pieces_root.clone())];
+        assert_eq!(layer.len(), 3 * 32);
+        // the typed structure encodes back to the original bytes
+        assert_eq!(to_bytes(&meta_info).unwrap().as_slice(), &raw_torrent[..]);
+
+        let raw_torrent = include_bytes!("example/hybrid.torrent");
+        let meta_info = MetaInfo::from_bytes(raw_torrent).unwrap();
+        assert!(meta_info.is_hybrid());
+        assert_eq!(
+            hex::encode(meta_info.info_hash()),
+            "b0d96589508010b9deb2b5b7806d9ce117e5eb28"
+        );
+        assert_eq!(
+            hex::encode(meta_info.info_hash_v2().unwrap().unwrap()),
+            "53c2fe0bab0462f6fbbfa51c005bb4463f4d18124ecfb090fbc97da9fae3baff"
+        );
+        assert_eq!(to_bytes(&meta_info).unwrap().as_slice(), &raw_torrent[..]);
+
+        let raw_torrent =
+            include_bytes!("example/archlinux-2011.08.19-netinstall-i686.iso.torrent");
+        let meta_info = MetaInfo::from_bytes(raw_torrent).unwrap();
+        assert!(!meta_info.is_v2());
+        assert!(meta_info.info_hash_v2().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_meta_info_file() {
+        let raw_torrent =
+            include_bytes!("example/archlinux-2011.08.19-netinstall-i686.iso.torrent");
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("a.torrent");
+        let copy_path = dir.path().join("b.torrent");
+        block_on(async {
+            fs::write(&path, &raw_torrent[..]).await.unwrap();
+            let meta_info = MetaInfo::from_file(&path).await.unwrap();
+            meta_info.to_file(&copy_path).await.unwrap();
+            assert_eq!(fs::read(&copy_path).await.unwrap(), &raw_torrent[..]);
+            let copy = MetaInfo::from_file(&copy_path).await.unwrap();
+            assert_eq!(copy.info_hash(), meta_info.info_hash());
+        });
+    }
 }
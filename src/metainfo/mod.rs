@@ -2,19 +2,20 @@
 
 mod address;
 pub use address::PeerAddress;
-pub(crate) use address::{ADDRESS_V4_LEN, ADDRESS_V6_LEN};
+pub(crate) use address::{from_compact_list, to_compact_list, ADDRESS_V4_LEN, ADDRESS_V6_LEN};
 
 mod error;
 pub use error::Error;
 
 mod info;
-pub use info::Info;
+pub use info::{default_file_filter, FileTreeNode, Info, V2File};
 
 mod metainfo;
-pub use metainfo::{MetaInfo, UrlList};
+pub use metainfo::{MetaInfo, MetaInfoBuilder, UrlList};
 
 mod piece;
 pub(crate) use piece::ID_LEN;
 pub use piece::{
-    HashPiece, HashPieces, PIECE_SIZE_1M, PIECE_SIZE_256_KB, PIECE_SIZE_2M, PIECE_SIZE_512_KB,
+    HashPiece, HashPieces, CLIENT_PREFIX, PIECE_SIZE_1M, PIECE_SIZE_256_KB, PIECE_SIZE_2M,
+    PIECE_SIZE_512_KB,
 };
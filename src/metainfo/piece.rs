@@ -1,9 +1,12 @@
-use super::error::Result;
+use super::error::{Error as MetaError, Result};
 use super::info::Info;
 use async_std::{
-    io::{self, Read, ReadExt},
-    task::ready,
+    fs::File,
+    io::{self, prelude::SeekExt, Read, ReadExt, SeekFrom},
+    task::{self, ready},
 };
+use data_encoding::BASE32;
+use futures::stream::{self, StreamExt, TryStreamExt};
 use rand::random;
 use serde::{
     de::{Error, Visitor},
@@ -11,11 +14,15 @@ use serde::{
 };
 use serde_bencode::ser::to_bytes;
 use sha1::{Digest, Sha1};
+use std::cmp::Ordering;
 use std::convert::TryInto;
 use std::fmt;
+use std::net::IpAddr;
 use std::ops::BitXor;
+use std::path::PathBuf;
 use std::pin::Pin;
 use std::result::Result as StdResult;
+use std::sync::Arc;
 use std::task::{Context, Poll};
 use std::usize;
 
@@ -24,6 +31,8 @@ pub const PIECE_SIZE_512_KB: u64 = 2 * PIECE_SIZE_256_KB;
 pub const PIECE_SIZE_1M: u64 = 2 * PIECE_SIZE_512_KB;
 pub const PIECE_SIZE_2M: u64 = 2 * PIECE_SIZE_1M;
 pub(crate) const ID_LEN: usize = 20;
+/// Azureus-style prefix of the peer ids generated by this client, see [`HashPiece::peer_id`].
+pub const CLIENT_PREFIX: &[u8; 8] = b"-RS0001-";
 
 /// HashPiece represents the SHA1 hash of the piece at the corresponding index.
 #[derive(Debug, PartialEq, Eq, Default, Clone, PartialOrd, Ord, Hash)]
@@ -39,12 +48,113 @@ impl HashPiece {
         Self(hash_val)
     }
 
+    /// Generate a peer id starting with the Azureus-style `client_prefix` such as `-RS0001-`,
+    /// the remaining 12 bytes are random.
+    pub fn peer_id(client_prefix: &[u8; 8]) -> Self {
+        let mut hash_val: [u8; ID_LEN] = random();
+        hash_val[..client_prefix.len()].copy_from_slice(client_prefix);
+        Self(hash_val)
+    }
+
+    /// Parse HashPiece from its 40 characters hex form.
+    pub fn from_hex(encoded: &str) -> Result<Self> {
+        let mut hash_val = [0; ID_LEN];
+        hex::decode_to_slice(encoded, &mut hash_val)?;
+        Ok(Self(hash_val))
+    }
+
+    /// Parse HashPiece from its 32 characters base32 form, case insensitive.
+    pub fn from_base32(encoded: &str) -> Result<Self> {
+        if encoded.len() != 32 {
+            return Err(MetaError::HashLength(encoded.len()));
+        }
+        let mut hash_val = [0; ID_LEN];
+        BASE32
+            .decode_mut(encoded.to_uppercase().as_bytes(), &mut hash_val)
+            .map_err(|e| e.error)?;
+        Ok(Self(hash_val))
+    }
+
+    /// Returns the 40 characters hex form of HashPiece.
+    pub fn to_hex(&self) -> String {
+        hex::encode(self.0)
+    }
+
     /// Returns the number of one in the binary representation of HashPiece.
     pub fn count_ones(&self) -> usize {
         self.0
             .iter()
             .fold(0, |res, byte| byte.count_ones() as usize + res)
     }
+
+    /// Generate a node id for `external_ip` as defined in https://www.bittorrent.org/beps/bep_0042.html,
+    /// the low three bits of `rand_seed` are mixed into the hash and the whole byte ends the id.
+    pub fn generate_secure(external_ip: IpAddr, rand_seed: u8) -> Self {
+        let crc = secure_prefix(external_ip, rand_seed);
+        let mut hash_val: [u8; ID_LEN] = random();
+        hash_val[0] = (crc >> 24) as u8;
+        hash_val[1] = (crc >> 16) as u8;
+        hash_val[2] = (crc >> 8) as u8 & 0xf8 | hash_val[2] & 0x07;
+        hash_val[ID_LEN - 1] = rand_seed;
+        Self(hash_val)
+    }
+
+    /// Is it a valid node id for `ip` according to bep_0042?
+    /// Ids of nodes in local networks are not restricted.
+    pub fn is_valid_for_ip(&self, ip: IpAddr) -> bool {
+        let is_local = match ip {
+            IpAddr::V4(ip) => ip.is_private() || ip.is_loopback() || ip.is_link_local(),
+            IpAddr::V6(ip) => ip.is_loopback(),
+        };
+        if is_local {
+            return true;
+        }
+        let crc = secure_prefix(ip, self.0[ID_LEN - 1]);
+        self.0[0] == (crc >> 24) as u8
+            && self.0[1] == (crc >> 16) as u8
+            && self.0[2] & 0xf8 == (crc >> 8) as u8 & 0xf8
+    }
+
+    /// Returns the number of leading zero bits in the binary representation of HashPiece,
+    /// for a XOR distance it is the length of the prefix shared by the two ids.
+    pub fn leading_zeros(&self) -> usize {
+        match self.0.iter().position(|byte| *byte != 0) {
+            Some(i) => i * 8 + self.0[i].leading_zeros() as usize,
+            None => ID_LEN * 8,
+        }
+    }
+
+    /// Returns the XOR distance between two ids, see bep_0005.
+    pub fn distance(&self, other: &HashPiece) -> HashPiece {
+        self ^ other
+    }
+
+    /// Compare `a` and `b` by their distance to `target`, the closer one is the lesser.
+    pub fn cmp_distance(target: &HashPiece, a: &HashPiece, b: &HashPiece) -> Ordering {
+        target.distance(a).cmp(&target.distance(b))
+    }
+}
+
+/// CRC32-C of the masked ip, whose top 21 bits prefix a secure node id.
+fn secure_prefix(ip: IpAddr, rand_seed: u8) -> u32 {
+    const V4_MASK: [u8; 4] = [0x03, 0x0f, 0x3f, 0xff];
+    const V6_MASK: [u8; 8] = [0x01, 0x03, 0x07, 0x0f, 0x1f, 0x3f, 0x7f, 0xff];
+    let mut masked = match ip {
+        IpAddr::V4(ip) => ip
+            .octets()
+            .iter()
+            .zip(V4_MASK.iter())
+            .map(|(octet, mask)| octet & mask)
+            .collect::<Vec<u8>>(),
+        IpAddr::V6(ip) => ip
+            .octets()
+            .iter()
+            .zip(V6_MASK.iter())
+            .map(|(octet, mask)| octet & mask)
+            .collect(),
+    };
+    masked[0] |= (rand_seed & 0x07) << 5;
+    crc32c::crc32c(&masked)
 }
 
 impl BitXor for &HashPiece {
@@ -59,6 +169,12 @@ impl BitXor for &HashPiece {
     }
 }
 
+impl fmt::Display for HashPiece {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_hex())
+    }
+}
+
 impl Serialize for HashPiece {
     fn serialize<S>(&self, serializer: S) -> StdResult<S::Ok, S::Error>
     where
@@ -134,12 +250,21 @@ impl AsMut<[u8]> for HashPiece {
 pub struct HashPieces(pub Vec<HashPiece>);
 
 impl HashPieces {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
     /// Create HashPieces by hashing the giving piece
     pub async fn hash_pieces<R: Read + Unpin>(
         piece_readers: Vec<R>,
         piece_length: u64,
     ) -> Result<Self> {
-        assert!(piece_readers.len() >= 1);
+        if piece_length == 0 {
+            return Err(MetaError::PieceLength(piece_length));
+        }
+        if piece_readers.is_empty() {
+            return Err(MetaError::NoReaders);
+        }
         let mut hasher = Sha1::new();
         let mut hash_vec = Vec::new();
         let mut buf: Vec<u8> = vec![0; piece_length as usize];
@@ -156,13 +281,12 @@ impl HashPieces {
                 }
                 n => {
                     hasher.update(&buf[index..index + n]);
+                    index += n;
                     if index == piece_length as usize {
                         index = 0;
                         let hash_chunk = HashPiece(hasher.finalize().into());
                         hash_vec.push(hash_chunk);
                         hasher = Sha1::new();
-                    } else {
-                        index += n;
                     }
                 }
             }
@@ -170,6 +294,65 @@ impl HashPieces {
 
         Ok(HashPieces(hash_vec))
     }
+
+    /// Create HashPieces by hashing the pieces of the giving files concurrently,
+    /// with at most `parallelism` pieces in flight.
+    /// Each file is given as its path and length, in torrent order
+    pub async fn hash_files_parallel(
+        files: Vec<(PathBuf, u64)>,
+        piece_length: u64,
+        parallelism: usize,
+    ) -> Result<Self> {
+        if piece_length == 0 {
+            return Err(MetaError::PieceLength(piece_length));
+        }
+        if parallelism == 0 {
+            return Err(MetaError::ZeroParallelism);
+        }
+        // (path, offset of the file in the torrent, length)
+        let mut regions = Vec::with_capacity(files.len());
+        let mut total_length = 0;
+        for (path, length) in files {
+            if length != 0 {
+                regions.push((path, total_length, length));
+                total_length += length;
+            }
+        }
+        let regions = Arc::new(regions);
+        let piece_count = (total_length + piece_length - 1) / piece_length;
+        let hash_vec = stream::iter(0..piece_count)
+            .map(|index| {
+                let regions = regions.clone();
+                let begin = index * piece_length;
+                let end = (begin + piece_length).min(total_length);
+                task::spawn(async move { hash_region(&regions, begin, end).await })
+            })
+            .buffered(parallelism)
+            .try_collect()
+            .await?;
+        Ok(HashPieces(hash_vec))
+    }
+}
+
+/// Hash the bytes in `begin..end` of the concatenated files by reading each
+/// overlapping file at its offset
+async fn hash_region(regions: &[(PathBuf, u64, u64)], begin: u64, end: u64) -> Result<HashPiece> {
+    let mut hasher = Sha1::new();
+    let mut buf = Vec::new();
+    for (path, offset, length) in regions {
+        let (file_begin, file_end) = (*offset, offset + length);
+        if file_end <= begin || file_begin >= end {
+            continue;
+        }
+        let read_begin = begin.max(file_begin);
+        let read_end = end.min(file_end);
+        let mut file = File::open(path).await?;
+        file.seek(SeekFrom::Start(read_begin - file_begin)).await?;
+        buf.resize((read_end - read_begin) as usize, 0);
+        file.read_exact(&mut buf).await?;
+        hasher.update(&buf);
+    }
+    Ok(HashPiece(hasher.finalize().into()))
 }
 
 impl Serialize for HashPieces {
@@ -271,7 +454,7 @@ mod tests {
     use async_std::task::block_on;
     use serde_bencode::{de::from_bytes, ser::to_bytes};
     use std::io::Write;
-    use tempfile::NamedTempFile;
+    use tempfile::{tempdir, NamedTempFile};
 
     #[test]
     fn test_chains() {
@@ -287,6 +470,116 @@ mod tests {
         })
     }
 
+    #[test]
+    fn test_leading_zeros() {
+        let a = HashPiece::rand_new();
+        let mut b = a.clone();
+        b.0[ID_LEN - 1] ^= 1;
+        assert_eq!((&a ^ &b).leading_zeros(), ID_LEN * 8 - 1);
+        b.0[0] ^= 0x20;
+        assert_eq!((&a ^ &b).leading_zeros(), 2);
+        assert_eq!((&a ^ &a).leading_zeros(), ID_LEN * 8);
+    }
+
+    #[test]
+    fn test_peer_id() {
+        let a = HashPiece::peer_id(b"-RS0001-");
+        let b = HashPiece::peer_id(b"-RS0001-");
+        assert_eq!(&a.as_ref()[..8], b"-RS0001-");
+        assert_eq!(&b.as_ref()[..8], b"-RS0001-");
+        assert_ne!(a.as_ref()[8..], b.as_ref()[8..]);
+    }
+
+    #[test]
+    fn test_hash_piece_encoding() {
+        let hex = "5b6e1d8a2ea3d5a8a0b4f37c1e3b8d9f0c2e4a61";
+        let from_hex = HashPiece::from_hex(hex).unwrap();
+        let from_base32 = HashPiece::from_base32("LNXB3CROUPK2RIFU6N6B4O4NT4GC4STB").unwrap();
+        assert_eq!(from_hex, from_base32);
+        assert_eq!(
+            HashPiece::from_base32("lnxb3croupk2rifu6n6b4o4nt4gc4stb").unwrap(),
+            from_hex
+        );
+        assert_eq!(from_hex.to_hex(), hex);
+        assert_eq!(from_hex.to_string(), hex);
+
+        assert!(HashPiece::from_hex(&hex[2..]).is_err());
+        assert!(HashPiece::from_hex(&hex.replace('5', "z")).is_err());
+        assert!(HashPiece::from_base32("LNXB3CROUPK2RIFU6N6B4O4NT4GC4ST").is_err());
+        assert!(HashPiece::from_base32("LNXB3CROUPK2RIFU6N6B4O4NT4GC4ST1").is_err());
+    }
+
+    #[test]
+    fn test_distance() {
+        let id = |last: u8| {
+            let mut hash_val = [0; ID_LEN];
+            hash_val[ID_LEN - 1] = last;
+            HashPiece::new(hash_val)
+        };
+        let target = id(0b0100);
+        assert_eq!(target.distance(&id(0b0110)), id(0b0010));
+        assert_eq!(target.distance(&id(0b1000)), id(0b1100));
+        assert_eq!(target.distance(&id(0b1000)).leading_zeros(), ID_LEN * 8 - 4);
+        assert_eq!(target.distance(&target), HashPiece::default());
+        assert_eq!(
+            HashPiece::cmp_distance(&target, &id(0b0110), &id(0b0001)),
+            Ordering::Less
+        );
+        assert_eq!(
+            HashPiece::cmp_distance(&target, &id(0b1000), &id(0b0000)),
+            Ordering::Greater
+        );
+
+        let mut ids = vec![id(0b1111), id(0b0000), id(0b0101), id(0b0100)];
+        ids.sort_by(|a, b| HashPiece::cmp_distance(&target, a, b));
+        assert_eq!(ids, vec![id(0b0100), id(0b0101), id(0b0000), id(0b1111)]);
+    }
+
+    #[test]
+    fn test_secure_id() {
+        // test vectors of bep_0042
+        for (ip, rand_seed, id) in [
+            (
+                "124.31.75.21",
+                1,
+                "5fbfbff10c5d6a4ec8a88e4c6ab4c28b95eee401",
+            ),
+            (
+                "21.75.31.124",
+                86,
+                "5a3ce9c14e7a08645677bbd1cfe7d8f956d53256",
+            ),
+            (
+                "65.23.51.170",
+                22,
+                "a5d43220bc8f112a3d426c84764f8c2a1150e616",
+            ),
+            (
+                "84.124.73.14",
+                65,
+                "1b0321dd1bb1fe518101ceef99462b947a01ff41",
+            ),
+            (
+                "43.213.53.83",
+                90,
+                "e56f6cbf5b7c4be0237986d5243b87aa6d51305a",
+            ),
+        ] {
+            let ip: IpAddr = ip.parse().unwrap();
+            let mut hash_val = [0; ID_LEN];
+            hash_val.copy_from_slice(&hex::decode(id).unwrap());
+            let id = HashPiece(hash_val);
+            assert!(id.is_valid_for_ip(ip));
+            let generated = HashPiece::generate_secure(ip, rand_seed);
+            assert_eq!(generated.0[..2], id.0[..2]);
+            assert_eq!(generated.0[2] & 0xf8, id.0[2] & 0xf8);
+            assert_eq!(generated.0[ID_LEN - 1], rand_seed);
+            assert!(generated.is_valid_for_ip(ip));
+            assert!(!generated.is_valid_for_ip("1.2.3.4".parse().unwrap()));
+        }
+        assert!(HashPiece::rand_new().is_valid_for_ip("192.168.1.1".parse().unwrap()));
+    }
+
     #[test]
     fn test_hashvec() {
         let chunk1 = HashPiece([
@@ -320,4 +613,68 @@ mod tests {
             assert_eq!(v, hashes.unwrap().0[0].0);
         });
     }
+
+    #[test]
+    fn test_gen_hashes_partial_piece() {
+        let readers = vec![&b"Hello Wo"[..], &b"rld!!"[..]];
+        block_on(async {
+            let hashes = HashPieces::hash_pieces(readers, 4).await.unwrap();
+            let expected: Vec<HashPiece> = b"Hello World!!"
+                .chunks(4)
+                .map(|chunk| HashPiece(Sha1::digest(chunk).into()))
+                .collect();
+            assert_eq!(expected.len(), 4);
+            assert_eq!(hashes.0, expected);
+
+            assert!(matches!(
+                HashPieces::hash_pieces(vec![&b"Hello"[..]], 0).await,
+                Err(MetaError::PieceLength(0))
+            ));
+            assert!(matches!(
+                HashPieces::hash_pieces(Vec::<&[u8]>::new(), 4).await,
+                Err(MetaError::NoReaders)
+            ));
+        });
+    }
+
+    #[test]
+    fn test_hash_files_parallel() {
+        let dir = tempdir().unwrap();
+        let contents: Vec<Vec<u8>> = vec![
+            (0..1000).map(|i| i as u8).collect(),
+            vec![],
+            (0..7).collect(),
+            (0..4096).map(|i| (i * 7) as u8).collect(),
+            vec![42; 333],
+        ];
+        let mut files = Vec::new();
+        for (i, content) in contents.iter().enumerate() {
+            let path = dir.path().join(i.to_string());
+            std::fs::write(&path, content).unwrap();
+            files.push((path, content.len() as u64));
+        }
+        block_on(async {
+            for &piece_length in &[1, 7, 256, 1000, 8192] {
+                let readers: Vec<&[u8]> = contents.iter().map(|c| &c[..]).collect();
+                let expected = HashPieces::hash_pieces(readers, piece_length)
+                    .await
+                    .unwrap();
+                assert!(matches!(
+                    HashPieces::hash_files_parallel(files.clone(), piece_length, 0).await,
+                    Err(MetaError::ZeroParallelism)
+                ));
+                for &parallelism in &[1, 4] {
+                    let hashes =
+                        HashPieces::hash_files_parallel(files.clone(), piece_length, parallelism)
+                            .await
+                            .unwrap();
+                    assert_eq!(hashes, expected);
+                }
+            }
+            assert!(matches!(
+                HashPieces::hash_files_parallel(files, 0, 4).await,
+                Err(MetaError::PieceLength(0))
+            ));
+        });
+    }
 }
@@ -0,0 +1,127 @@
+use super::error::{Error, Result};
+use super::message::MAX_MESSAGE_LEN;
+use bitvec::prelude::{BitVec, Msb0};
+
+/// Largest number of pieces a bitfield message can describe.
+pub(crate) const MAX_PIECE_COUNT: usize = (MAX_MESSAGE_LEN - 1) * 8;
+
+/// BitField represents the pieces a peer has.
+/// The high bit in the first byte corresponds to piece index 0,
+/// spare bits at the end are set to zero.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct BitField(BitVec<Msb0, u8>);
+
+impl BitField {
+    /// Create a bitfield of `piece_count` pieces, none of them set.
+    pub fn new(piece_count: usize) -> Self {
+        Self(BitVec::repeat(false, piece_count))
+    }
+
+    /// Create a bitfield of `piece_count` pieces, all of them set.
+    pub fn full(piece_count: usize) -> Self {
+        let mut bits = BitVec::repeat(true, piece_count);
+        bits.set_uninitialized(false);
+        Self(bits)
+    }
+
+    /// Create a bitfield from the payload of a bitfield message without knowing the piece count.
+    pub(crate) fn from_raw(bytes: &[u8]) -> Self {
+        Self(BitVec::from_vec(bytes.to_vec()))
+    }
+
+    /// Number of bytes of the payload of a bitfield message of `piece_count` pieces.
+    pub(crate) fn byte_len(piece_count: usize) -> usize {
+        (piece_count + 7) / 8
+    }
+
+    /// Create a bitfield of `piece_count` pieces from the payload of a bitfield message,
+    /// the payload must be exactly large enough and the spare bits must be zero.
+    pub fn from_bytes(bytes: &[u8], piece_count: usize) -> Result<Self> {
+        if bytes.len() != Self::byte_len(piece_count) {
+            return Err(Error::InvalidBitField(piece_count));
+        }
+        let mut bits = BitVec::from_vec(bytes.to_vec());
+        if bits[piece_count..].any() {
+            return Err(Error::InvalidBitField(piece_count));
+        }
+        bits.truncate(piece_count);
+        Ok(Self(bits))
+    }
+
+    /// Returns the payload of a bitfield message.
+    pub fn as_bytes(&self) -> &[u8] {
+        self.0.as_raw_slice()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Does the peer have the piece?
+    pub fn has(&self, index: usize) -> bool {
+        matches!(self.0.get(index), Some(bit) if *bit)
+    }
+
+    /// Mark the piece as available, growing the bitfield if needed.
+    pub fn set(&mut self, index: usize) {
+        if index >= self.0.len() {
+            self.0.resize(index + 1, false);
+        }
+        self.0.set(index, true);
+    }
+
+    /// Returns the number of available pieces.
+    pub fn count_set(&self) -> usize {
+        self.0.count_ones()
+    }
+
+    /// Are all of the `total_pieces` pieces available?
+    pub fn is_complete(&self, total_pieces: usize) -> bool {
+        self.0.len() >= total_pieces && self.0[..total_pieces].all()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bitfield() {
+        let mut bitfield = BitField::from_bytes(&[0b1010_0000, 0b0100_0000], 10).unwrap();
+        assert_eq!(bitfield.len(), 10);
+        assert!(bitfield.has(0));
+        assert!(!bitfield.has(1));
+        assert!(bitfield.has(2));
+        assert!(bitfield.has(9));
+        assert!(!bitfield.has(10));
+        assert_eq!(bitfield.count_set(), 3);
+        assert!(!bitfield.is_complete(10));
+
+        bitfield.set(1);
+        assert_eq!(bitfield.as_bytes(), &[0b1110_0000, 0b0100_0000]);
+        assert!(BitField::full(10).is_complete(10));
+        assert_eq!(BitField::full(10).as_bytes(), &[0xff, 0b1100_0000]);
+        assert!(!BitField::new(10).is_complete(10));
+        bitfield.set(12);
+        assert_eq!(bitfield.len(), 13);
+    }
+
+    #[test]
+    fn test_bitfield_validation() {
+        // a spare bit is set
+        assert!(matches!(
+            BitField::from_bytes(&[0xff, 0b0010_0000], 10),
+            Err(Error::InvalidBitField(10))
+        ));
+        // too short and too long
+        assert!(BitField::from_bytes(&[0xff], 10).is_err());
+        assert!(BitField::from_bytes(&[0xff, 0, 0], 10).is_err());
+        assert!(BitField::from_bytes(&[0xff, 0b1100_0000], 10)
+            .unwrap()
+            .is_complete(10));
+    }
+}
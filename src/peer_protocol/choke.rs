@@ -0,0 +1,210 @@
+//! Tit-for-tat choking, local uploads to the peers it downloads from the fastest.
+//!
+//! The manager doesn't own the sessions, so the caller drives it: it runs a round with
+//! [`ChokeManager::rechoke`] every [`RECHOKE_INTERVAL`] and in between feeds
+//! [`ChokeManager::update_peer`] from the events of each session, the download rate measured
+//! by a [`Rate`](super::Rate) recording the blocks of `SessionEvent::BlockReceived` and the
+//! interest reported by `SessionEvent::Interested`.
+
+use super::session::Command;
+use async_std::channel::Sender;
+use rand::seq::IteratorRandom;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use std::time::Duration;
+
+/// Peers are ranked again every ten seconds.
+pub const RECHOKE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The optimistic unchoke moves to another peer every third round.
+const OPTIMISTIC_ROUNDS: usize = 3;
+
+/// ChokePeer is the state of a session as seen by the choke manager.
+#[derive(Debug)]
+struct ChokePeer {
+    /// communication channel to the session
+    cmd_tx: Sender<Command>,
+    /// bytes per second the remote peer uploads to local
+    download_rate: f64,
+    /// remote peer is interested in pieces of local
+    interested: bool,
+    /// local is choking the remote peer
+    choked: bool,
+}
+
+/// ChokeManager decides which sessions are unchoked, `K` identifies a session.
+#[derive(Debug)]
+pub struct ChokeManager<K> {
+    peers: HashMap<K, ChokePeer>,
+    /// number of peers unchoked for their download rate
+    unchoke_slots: usize,
+    /// peer unchoked regardless of its download rate
+    optimistic: Option<K>,
+    round: usize,
+}
+
+impl<K: Eq + Hash + Clone> ChokeManager<K> {
+    pub fn new(unchoke_slots: usize) -> Self {
+        ChokeManager {
+            peers: HashMap::new(),
+            unchoke_slots,
+            optimistic: None,
+            round: 0,
+        }
+    }
+
+    /// Add the session driven by `cmd_tx`, a new session starts choked.
+    pub fn add_peer(&mut self, key: K, cmd_tx: Sender<Command>) {
+        self.peers.insert(
+            key,
+            ChokePeer {
+                cmd_tx,
+                download_rate: 0.0,
+                interested: false,
+                choked: true,
+            },
+        );
+    }
+
+    pub fn remove_peer(&mut self, key: &K) {
+        self.peers.remove(key);
+        if self.optimistic.as_ref() == Some(key) {
+            self.optimistic = None;
+        }
+    }
+
+    /// Update the download rate and the interest of the remote peer of a session.
+    pub fn update_peer(&mut self, key: &K, download_rate: f64, interested: bool) {
+        if let Some(peer) = self.peers.get_mut(key) {
+            peer.download_rate = download_rate;
+            peer.interested = interested;
+        }
+    }
+
+    /// Is local unchoking the session?
+    pub fn is_unchoked(&self, key: &K) -> bool {
+        matches!(self.peers.get(key), Some(peer) if !peer.choked)
+    }
+
+    /// Run a round of the choking algorithm, sessions whose state changes are sent `Choke` or `Unchoke`.
+    /// The optimistic unchoke moves every third round.
+    pub async fn rechoke(&mut self) {
+        let unchoked = self.select_unchoked();
+        for (key, peer) in self.peers.iter_mut() {
+            let choked = !unchoked.contains(key);
+            if peer.choked != choked {
+                peer.choked = choked;
+                let cmd = if choked {
+                    Command::Choke
+                } else {
+                    Command::Unchoke
+                };
+                // the receiver may have gone, the session is removed later
+                let _ = peer.cmd_tx.send(cmd).await;
+            }
+        }
+    }
+
+    /// Interested peers with the best download rates, plus an optimistic unchoke among the others.
+    fn select_unchoked(&mut self) -> HashSet<K> {
+        let mut interested: Vec<(&K, &ChokePeer)> = self
+            .peers
+            .iter()
+            .filter(|(_, peer)| peer.interested)
+            .collect();
+        interested.sort_by(|(_, a), (_, b)| {
+            b.download_rate
+                .partial_cmp(&a.download_rate)
+                .unwrap_or(Ordering::Equal)
+        });
+        let mut unchoked: HashSet<K> = interested
+            .iter()
+            .take(self.unchoke_slots)
+            .map(|(key, _)| (*key).clone())
+            .collect();
+
+        // an optimistic unchoke that earned a regular slot is replaced
+        let keep_optimistic = self.round % OPTIMISTIC_ROUNDS != 0
+            && matches!(
+                self.optimistic.as_ref().and_then(|key| self.peers.get_key_value(key)),
+                Some((key, peer)) if peer.interested && !unchoked.contains(key)
+            );
+        if !keep_optimistic {
+            self.optimistic = interested
+                .iter()
+                .map(|(key, _)| *key)
+                .filter(|key| !unchoked.contains(*key))
+                .choose(&mut rand::thread_rng())
+                .cloned();
+        }
+        self.round += 1;
+        unchoked.extend(self.optimistic.clone());
+        unchoked
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_std::channel::{unbounded, Receiver};
+    use async_std::task::block_on;
+
+    #[test]
+    fn test_choke_ranking() {
+        let mut manager = ChokeManager::new(2);
+        let mut receivers: Vec<Receiver<Command>> = Vec::new();
+        for (key, rate, interested) in [
+            (0, 100.0, true),
+            (1, 500.0, true),
+            (2, 300.0, true),
+            (3, 900.0, false),
+            (4, 50.0, true),
+        ] {
+            let (cmd_tx, cmd_rx) = unbounded();
+            manager.add_peer(key, cmd_tx);
+            manager.update_peer(&key, rate, interested);
+            receivers.push(cmd_rx);
+        }
+
+        block_on(async {
+            manager.rechoke().await;
+            // the fastest interested peers and one optimistic unchoke among the rest
+            assert!(manager.is_unchoked(&1));
+            assert!(manager.is_unchoked(&2));
+            assert!(!manager.is_unchoked(&3));
+            let optimistic = manager.optimistic.unwrap();
+            assert!(optimistic == 0 || optimistic == 4);
+            assert_eq!(receivers[1].try_recv().unwrap(), Command::Unchoke);
+            assert_eq!(receivers[2].try_recv().unwrap(), Command::Unchoke);
+            assert_eq!(receivers[optimistic].try_recv().unwrap(), Command::Unchoke);
+            assert!(receivers[3].try_recv().is_err());
+
+            // the optimistic unchoke survives the next round and only changes are sent
+            manager.rechoke().await;
+            assert_eq!(manager.optimistic, Some(optimistic));
+            for receiver in receivers.iter() {
+                assert!(receiver.try_recv().is_err());
+            }
+
+            // the peer no longer interested loses its slot to the optimistic unchoke,
+            // which is replaced by a new optimistic unchoke
+            manager.update_peer(&2, 300.0, false);
+            manager.update_peer(&optimistic, 1000.0, true);
+            manager.rechoke().await;
+            let other = if optimistic == 0 { 4 } else { 0 };
+            assert!(manager.is_unchoked(&1));
+            assert!(manager.is_unchoked(&optimistic));
+            assert!(manager.is_unchoked(&other));
+            assert_eq!(manager.optimistic, Some(other));
+            assert!(receivers[1].try_recv().is_err());
+            assert!(receivers[optimistic].try_recv().is_err());
+            assert_eq!(receivers[other].try_recv().unwrap(), Command::Unchoke);
+            assert!(!manager.is_unchoked(&2));
+            assert_eq!(receivers[2].try_recv().unwrap(), Command::Choke);
+
+            manager.remove_peer(&other);
+            assert_eq!(manager.optimistic, None);
+        });
+    }
+}
@@ -0,0 +1,250 @@
+//! Coordinates the block requests of all sessions of a torrent, including the endgame mode
+//! where the last blocks are requested from every unchoked session.
+
+use super::session::{BlockRequest, Command};
+use async_std::channel::Sender;
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+
+/// CoordinatedSession is the state of a session as seen by the coordinator.
+#[derive(Debug)]
+struct CoordinatedSession {
+    /// communication channel to the session
+    cmd_tx: Sender<Command>,
+    /// the remote peer is unchoking local, so requests are sent right away
+    unchoked: bool,
+}
+
+/// DownloadCoordinator tracks which sessions a block is requested from, `K` identifies a session.
+#[derive(Debug)]
+pub struct DownloadCoordinator<K> {
+    sessions: HashMap<K, CoordinatedSession>,
+    /// blocks requested and not received yet, with the sessions they are requested from
+    requested: HashMap<BlockRequest, HashSet<K>>,
+    /// number of blocks of the torrent not received yet
+    missing: usize,
+    /// endgame starts once at most this many blocks are missing
+    endgame_threshold: usize,
+    endgame: bool,
+}
+
+impl<K: Eq + Hash + Clone> DownloadCoordinator<K> {
+    /// Create a coordinator of a download missing `missing` blocks,
+    /// it starts in endgame if there are no more than `endgame_threshold` of them.
+    pub fn new(missing: usize, endgame_threshold: usize) -> Self {
+        DownloadCoordinator {
+            sessions: HashMap::new(),
+            requested: HashMap::new(),
+            missing,
+            endgame_threshold,
+            endgame: missing <= endgame_threshold,
+        }
+    }
+
+    /// Add the session driven by `cmd_tx`, the remote peer of a new session chokes local.
+    pub fn add_session(&mut self, key: K, cmd_tx: Sender<Command>) {
+        self.sessions.insert(
+            key,
+            CoordinatedSession {
+                cmd_tx,
+                unchoked: false,
+            },
+        );
+    }
+
+    /// Remove a session, the blocks requested from it are left to the other sessions.
+    pub fn remove_session(&mut self, key: &K) {
+        self.sessions.remove(key);
+        for requesters in self.requested.values_mut() {
+            requesters.remove(key);
+        }
+    }
+
+    /// Record whether the remote peer of the session unchokes local.
+    /// In endgame a session getting unchoked is sent the requests of every block in flight.
+    pub async fn set_unchoked(&mut self, key: &K, unchoked: bool) {
+        let session = match self.sessions.get_mut(key) {
+            Some(session) => session,
+            None => return,
+        };
+        session.unchoked = unchoked;
+        if !unchoked || !self.endgame {
+            return;
+        }
+        for (block, requesters) in self.requested.iter_mut() {
+            if requesters.insert(key.clone()) {
+                send(&session.cmd_tx, block.to_request()).await;
+            }
+        }
+    }
+
+    pub fn is_endgame(&self) -> bool {
+        self.endgame
+    }
+
+    /// Request a block from the session, in endgame also from every unchoked session.
+    pub async fn request(
+        &mut self,
+        key: &K,
+        piece_index: usize,
+        block_begin: usize,
+        block_length: usize,
+    ) {
+        let block = BlockRequest {
+            piece_index,
+            block_begin,
+            block_length,
+        };
+        let requesters = self.requested.entry(block).or_default();
+        for (other, session) in self.sessions.iter() {
+            if (other == key || (self.endgame && session.unchoked))
+                && requesters.insert(other.clone())
+            {
+                send(&session.cmd_tx, block.to_request()).await;
+            }
+        }
+    }
+
+    /// Record a block received by the session, the other sessions it is requested from cancel it.
+    pub async fn block_received(
+        &mut self,
+        key: &K,
+        piece_index: usize,
+        block_begin: usize,
+        block_length: usize,
+    ) {
+        let block = BlockRequest {
+            piece_index,
+            block_begin,
+            block_length,
+        };
+        // a block received twice, or never requested, doesn't count
+        let requesters = match self.requested.remove(&block) {
+            Some(requesters) => requesters,
+            None => return,
+        };
+        self.missing = self.missing.saturating_sub(1);
+        for requester in requesters.iter().filter(|requester| *requester != key) {
+            if let Some(session) = self.sessions.get(requester) {
+                send(&session.cmd_tx, block.to_cancel()).await;
+            }
+        }
+        if !self.endgame && self.missing <= self.endgame_threshold {
+            self.enter_endgame().await;
+        }
+    }
+
+    /// Request every block in flight from every unchoked session,
+    /// a choked session would only send them once the blocks may have arrived.
+    async fn enter_endgame(&mut self) {
+        self.endgame = true;
+        for (block, requesters) in self.requested.iter_mut() {
+            for (key, session) in self.sessions.iter() {
+                if session.unchoked && requesters.insert(key.clone()) {
+                    send(&session.cmd_tx, block.to_request()).await;
+                }
+            }
+        }
+    }
+}
+
+async fn send(cmd_tx: &Sender<Command>, cmd: Command) {
+    // the receiver may have gone, the session is removed later
+    let _ = cmd_tx.send(cmd).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_std::channel::{unbounded, Receiver};
+    use async_std::task::block_on;
+
+    #[test]
+    fn test_endgame() {
+        block_on(async {
+            let mut coordinator = DownloadCoordinator::new(3, 2);
+            let mut receivers = Vec::new();
+            for key in 0..4 {
+                let (cmd_tx, cmd_rx) = unbounded();
+                coordinator.add_session(key, cmd_tx);
+                receivers.push(cmd_rx);
+            }
+            // the last session stays choked
+            for key in 0..3 {
+                coordinator.set_unchoked(&key, true).await;
+            }
+            let request = |piece_index| Command::RequestPiece {
+                piece_index,
+                block_begin: 0,
+                block_length: 16,
+            };
+            for (key, rx) in receivers.iter().enumerate().take(3) {
+                coordinator.request(&key, key, 0, 16).await;
+                assert_eq!(rx.try_recv().unwrap(), request(key));
+                assert!(rx.try_recv().is_err());
+            }
+            assert!(!coordinator.is_endgame());
+
+            // two blocks missing, the others are requested from every unchoked session
+            coordinator.block_received(&0, 0, 0, 16).await;
+            assert!(coordinator.is_endgame());
+            let sorted_requests = |rx: &Receiver<Command>| {
+                let mut requests: Vec<_> = std::iter::from_fn(|| rx.try_recv().ok()).collect();
+                requests.sort_by_key(|cmd| match *cmd {
+                    Command::RequestPiece { piece_index, .. } => piece_index,
+                    _ => unreachable!(),
+                });
+                requests
+            };
+            assert_eq!(sorted_requests(&receivers[0]), vec![request(1), request(2)]);
+            assert_eq!(receivers[1].try_recv().unwrap(), request(2));
+            assert_eq!(receivers[2].try_recv().unwrap(), request(1));
+            assert!(receivers[3].try_recv().is_err());
+
+            // the choked session gets the blocks in flight once it is unchoked
+            coordinator.set_unchoked(&3, true).await;
+            assert_eq!(sorted_requests(&receivers[3]), vec![request(1), request(2)]);
+
+            // the block arrives from one session, the others cancel it
+            coordinator.block_received(&2, 1, 0, 16).await;
+            let cancel = Command::CancelPiece {
+                piece_index: 1,
+                block_begin: 0,
+                block_length: 16,
+            };
+            assert_eq!(receivers[0].try_recv().unwrap(), cancel);
+            assert_eq!(receivers[1].try_recv().unwrap(), cancel);
+            assert!(receivers[2].try_recv().is_err());
+            assert_eq!(receivers[3].try_recv().unwrap(), cancel);
+
+            // a duplicate doesn't cancel anything
+            coordinator.block_received(&0, 1, 0, 16).await;
+            assert!(receivers.iter().all(|rx| rx.try_recv().is_err()));
+        });
+    }
+
+    #[test]
+    fn test_endgame_from_start() {
+        block_on(async {
+            // few enough blocks are missing to start in endgame
+            let mut coordinator = DownloadCoordinator::new(2, 2);
+            assert!(coordinator.is_endgame());
+            let mut receivers = Vec::new();
+            for key in 0..3 {
+                let (cmd_tx, cmd_rx) = unbounded();
+                coordinator.add_session(key, cmd_tx);
+                receivers.push(cmd_rx);
+            }
+            coordinator.set_unchoked(&1, true).await;
+            coordinator.request(&0, 0, 0, 16).await;
+            let request = Command::RequestPiece {
+                piece_index: 0,
+                block_begin: 0,
+                block_length: 16,
+            };
+            assert_eq!(receivers[0].try_recv().unwrap(), request);
+            assert_eq!(receivers[1].try_recv().unwrap(), request);
+            assert!(receivers[2].try_recv().is_err());
+        });
+    }
+}
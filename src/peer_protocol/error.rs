@@ -1,4 +1,3 @@
-use bitvec::ptr::BitSpanError;
 use thiserror::Error;
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -13,8 +12,42 @@ pub enum Error {
     MessageEndUnexpected,
     #[error("Message Type Num {0} Not Supposrt")]
     MessageTypeNotSupport(u8),
-    #[error("Create BitField from &[u8] failed, err:{0:?}")]
-    BitSpanError(#[from] BitSpanError<u8>),
+    #[error("BitField doesn't match {0} pieces")]
+    InvalidBitField(usize),
+    #[error("Handshake protocol string is not BitTorrent protocol")]
+    InvalidProtocol,
     #[error("Receive info_hash that not currently serving")]
     InvaildInfoHash,
+    #[error("{0}")]
+    Bencode(#[from] serde_bencode::Error),
+    #[error("{0}")]
+    MetaInfo(#[from] crate::metainfo::Error),
+    #[error("Remote peer doesn't support extension {0}")]
+    ExtensionNotSupport(&'static str),
+    #[error("Invalid ut_metadata message")]
+    InvalidMetadataMessage,
+    #[error("Invalid metadata size {0}")]
+    InvalidMetadataSize(usize),
+    #[error("Remote peer rejected metadata piece {0}")]
+    MetadataRejected(usize),
+    #[error("Metadata doesn't match info_hash")]
+    MetadataHashMismatch,
+    #[error("Invalid ut_pex message")]
+    InvalidPexMessage,
+    #[error("MSE handshake failed: {0}")]
+    EncryptionHandshake(&'static str),
+    #[error("Piece {0} is out of range")]
+    InvalidPiece(usize),
+    #[error("Piece length {0} is invalid")]
+    InvalidPieceLength(usize),
+    #[error("Invalid block of piece {piece_index} at {block_begin} with length {block_length}")]
+    InvalidBlock {
+        piece_index: usize,
+        block_begin: usize,
+        block_length: usize,
+    },
+    #[error("Piece {0} misses blocks")]
+    PieceIncomplete(usize),
+    #[error("Piece {0} doesn't match its hash")]
+    PieceHashMismatch(usize),
 }
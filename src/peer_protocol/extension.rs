@@ -0,0 +1,83 @@
+//! This module implements the extension protocol defined in https://www.bittorrent.org/beps/bep_0010.html
+
+use super::error::Result;
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use serde_bencode::{de::from_bytes, ser::to_bytes};
+use std::collections::{BTreeMap, HashMap};
+
+/// Extended message id reserved for the extended handshake.
+pub(crate) const EXTENDED_HANDSHAKE_ID: u8 = 0;
+
+/// ExtendedHandshake is the bencoded payload of the extended message with id 0.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Default, Clone)]
+pub struct ExtendedHandshake {
+    /// Dictionary of supported extension messages which maps names of extensions to an extended message id
+    #[serde(default)]
+    pub m: BTreeMap<String, u8>,
+    /// Local TCP listen port
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub p: Option<u16>,
+    /// Size of the info dictionary in bytes, see bep_0009
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub metadata_size: Option<usize>,
+    /// The number of outstanding request messages this client supports without dropping any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub reqq: Option<usize>,
+}
+
+impl ExtendedHandshake {
+    pub fn from_payload(payload: &[u8]) -> Result<Self> {
+        Ok(from_bytes(payload)?)
+    }
+
+    pub fn to_payload(&self) -> Result<Bytes> {
+        Ok(to_bytes(self)?.into())
+    }
+
+    /// Merge the announced extension ids into `extensions`.
+    /// An id of zero means the extension is disabled.
+    pub fn merge_into(&self, extensions: &mut HashMap<String, u8>) {
+        for (name, id) in self.m.iter() {
+            if *id == 0 {
+                extensions.remove(name);
+            } else {
+                extensions.insert(name.clone(), *id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extended_handshake() {
+        let payload = b"d1:md11:ut_metadatai3e6:ut_pexi1e11:lt_donthavei0ee\
+            13:metadata_sizei31235e1:pi6881e4:reqqi255e1:v14:uTorrent 3.5.5\
+            6:yourip4:\x7f\x00\x00\x01e";
+        let handshake = ExtendedHandshake::from_payload(&payload[..]).unwrap();
+        assert_eq!(handshake.m.get("ut_metadata"), Some(&3));
+        assert_eq!(handshake.m.get("ut_pex"), Some(&1));
+        assert_eq!(handshake.p, Some(6881));
+        assert_eq!(handshake.metadata_size, Some(31235));
+        assert_eq!(handshake.reqq, Some(255));
+
+        let mut extensions = HashMap::new();
+        extensions.insert("lt_donthave".to_string(), 7);
+        handshake.merge_into(&mut extensions);
+        assert_eq!(extensions.len(), 2);
+        assert_eq!(extensions.get("ut_metadata"), Some(&3));
+        assert!(!extensions.contains_key("lt_donthave"));
+
+        let encoded = handshake.to_payload().unwrap();
+        assert_eq!(
+            ExtendedHandshake::from_payload(&encoded).unwrap(),
+            handshake
+        );
+    }
+}
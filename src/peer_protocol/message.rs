@@ -1,15 +1,47 @@
 use crate::metainfo::HashPiece;
+use crate::peer_protocol::bitfield::BitField;
 use crate::peer_protocol::error::Error;
 use asynchronous_codec::{Decoder, Encoder};
-use bitvec::prelude::{BitVec, Lsb0};
 use bytes::{Buf, BufMut, Bytes};
+use std::cmp::Ordering;
+
+/// Size of the big-endian length prefix of every message.
+const LENGTH_PREFIX_LEN: usize = 4;
+/// Upper bound of a single message, large enough for a bitfield or a 16KiB block.
+pub(crate) const MAX_MESSAGE_LEN: usize = 1 << 20;
+/// Size of the handshake: <pstrlen><pstr><reserved><info_hash><peer_id>.
+pub(crate) const HANDSHAKE_LEN: usize = 1 + 19 + 8 + 20 + 20;
+
+const MESSAGE_CHOKE: u8 = 0;
+const MESSAGE_UNCHOKE: u8 = 1;
+const MESSAGE_INTERESTED: u8 = 2;
+const MESSAGE_NOT_INTERESTED: u8 = 3;
+const MESSAGE_HAVE: u8 = 4;
+const MESSAGE_BITFIELD: u8 = 5;
+const MESSAGE_REQUEST: u8 = 6;
+const MESSAGE_PIECE: u8 = 7;
+const MESSAGE_CANCEL: u8 = 8;
+const MESSAGE_PORT: u8 = 9;
+const MESSAGE_SUGGEST_PIECE: u8 = 0x0D;
+const MESSAGE_HAVE_ALL: u8 = 0x0E;
+const MESSAGE_HAVE_NONE: u8 = 0x0F;
+const MESSAGE_REJECT_REQUEST: u8 = 0x10;
+const MESSAGE_ALLOWED_FAST: u8 = 0x11;
+const MESSAGE_EXTENDED: u8 = 20;
+
+/// Reserved bit (byte 5, 0x10) advertising the extension protocol, see bep_0010
+const RESERVED_EXTENSION_PROTOCOL: (usize, u8) = (5, 0x10);
+/// Reserved bit (byte 7, 0x04) advertising the fast extension, see bep_0006
+const RESERVED_FAST_EXTENSION: (usize, u8) = (7, 0x04);
+/// Reserved bit (byte 7, 0x01) advertising a DHT node, see bep_0005
+const RESERVED_DHT: (usize, u8) = (7, 0x01);
 
 /// Meesage is the message used by the peer protocol.
 /// All of the remaining messages in the protocol take the form of <length prefix><message ID><payload>.
 /// The length prefix is a four byte big-endian value.
 /// The message ID is a single decimal byte.
 /// The payload is message dependent.
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum Message {
     KeepAlive,
     Choke,
@@ -20,7 +52,7 @@ pub enum Message {
         piece_index: usize,
     },
     BitField {
-        bitfield: BitVec<Lsb0, u8>,
+        bitfield: BitField,
     },
     Request {
         /// integer specifying the zero-based piece index
@@ -43,6 +75,30 @@ pub enum Message {
     Port {
         port: u16,
     },
+    /// Fast extension: advise the remote peer to download the piece
+    SuggestPiece {
+        piece_index: usize,
+    },
+    /// Fast extension: the sender has all pieces, replaces the bitfield
+    HaveAll,
+    /// Fast extension: the sender has no pieces, replaces the bitfield
+    HaveNone,
+    /// Fast extension: the request will not be satisfied
+    RejectRequest {
+        piece_index: usize,
+        block_begin: usize,
+        block_length: usize,
+    },
+    /// Fast extension: the piece may be requested even while the sender chokes
+    AllowedFast {
+        piece_index: usize,
+    },
+    /// Extension protocol message, see bep_0010
+    Extended {
+        /// 0 for the extended handshake, otherwise the id negotiated for an extension
+        extended_id: u8,
+        payload: Bytes,
+    },
 }
 
 pub(crate) struct MessageCodec;
@@ -56,10 +112,95 @@ impl Encoder for MessageCodec {
         item: Self::Item,
         dst: &mut asynchronous_codec::BytesMut,
     ) -> Result<(), Self::Error> {
-        todo!()
+        match item {
+            Message::KeepAlive => dst.put_u32(0),
+            Message::Choke => put_header(dst, MESSAGE_CHOKE, 0),
+            Message::UnChoke => put_header(dst, MESSAGE_UNCHOKE, 0),
+            Message::Intersted => put_header(dst, MESSAGE_INTERESTED, 0),
+            Message::NotInterested => put_header(dst, MESSAGE_NOT_INTERESTED, 0),
+            Message::Have { piece_index } => {
+                put_header(dst, MESSAGE_HAVE, 4);
+                dst.put_u32(piece_index as u32);
+            }
+            Message::BitField { bitfield } => {
+                let raw = bitfield.as_bytes();
+                put_header(dst, MESSAGE_BITFIELD, raw.len());
+                dst.put_slice(raw);
+            }
+            Message::Request {
+                piece_index,
+                block_begin,
+                block_length,
+            } => {
+                put_header(dst, MESSAGE_REQUEST, 12);
+                dst.put_u32(piece_index as u32);
+                dst.put_u32(block_begin as u32);
+                dst.put_u32(block_length as u32);
+            }
+            Message::Piece {
+                piece_index,
+                block_begin,
+                block_data,
+            } => {
+                put_header(dst, MESSAGE_PIECE, 8 + block_data.len());
+                dst.put_u32(piece_index as u32);
+                dst.put_u32(block_begin as u32);
+                dst.put_slice(&block_data);
+            }
+            Message::Cancel {
+                piece_index,
+                block_begin,
+                block_length,
+            } => {
+                put_header(dst, MESSAGE_CANCEL, 12);
+                dst.put_u32(piece_index as u32);
+                dst.put_u32(block_begin as u32);
+                dst.put_u32(block_length as u32);
+            }
+            Message::Port { port } => {
+                put_header(dst, MESSAGE_PORT, 2);
+                dst.put_u16(port);
+            }
+            Message::SuggestPiece { piece_index } => {
+                put_header(dst, MESSAGE_SUGGEST_PIECE, 4);
+                dst.put_u32(piece_index as u32);
+            }
+            Message::HaveAll => put_header(dst, MESSAGE_HAVE_ALL, 0),
+            Message::HaveNone => put_header(dst, MESSAGE_HAVE_NONE, 0),
+            Message::RejectRequest {
+                piece_index,
+                block_begin,
+                block_length,
+            } => {
+                put_header(dst, MESSAGE_REJECT_REQUEST, 12);
+                dst.put_u32(piece_index as u32);
+                dst.put_u32(block_begin as u32);
+                dst.put_u32(block_length as u32);
+            }
+            Message::AllowedFast { piece_index } => {
+                put_header(dst, MESSAGE_ALLOWED_FAST, 4);
+                dst.put_u32(piece_index as u32);
+            }
+            Message::Extended {
+                extended_id,
+                payload,
+            } => {
+                put_header(dst, MESSAGE_EXTENDED, 1 + payload.len());
+                dst.put_u8(extended_id);
+                dst.put_slice(&payload);
+            }
+        }
+        Ok(())
     }
 }
 
+/// Write the length prefix and message id of a message whose payload is `payload_len` bytes.
+fn put_header(dst: &mut asynchronous_codec::BytesMut, id: u8, payload_len: usize) {
+    dst.reserve(LENGTH_PREFIX_LEN + 1 + payload_len);
+    dst.put_u32(1 + payload_len as u32);
+    dst.put_u8(id);
+}
+
 impl Decoder for MessageCodec {
     type Item = Message;
     type Error = Error;
@@ -68,11 +209,139 @@ impl Decoder for MessageCodec {
         &mut self,
         src: &mut asynchronous_codec::BytesMut,
     ) -> Result<Option<Self::Item>, Self::Error> {
-        todo!()
+        if src.len() < LENGTH_PREFIX_LEN {
+            return Ok(None);
+        }
+        let length = u32::from_be_bytes([src[0], src[1], src[2], src[3]]) as usize;
+        if length > MAX_MESSAGE_LEN {
+            return Err(Error::WrongMessageLength);
+        }
+        if src.len() < LENGTH_PREFIX_LEN + length {
+            // wait until the whole frame is buffered
+            src.reserve(LENGTH_PREFIX_LEN + length - src.len());
+            return Ok(None);
+        }
+        src.advance(LENGTH_PREFIX_LEN);
+        if length == 0 {
+            return Ok(Some(Message::KeepAlive));
+        }
+        let mut frame = src.split_to(length);
+        let id = frame.get_u8();
+        let payload_len = length - 1;
+        let message = match id {
+            MESSAGE_CHOKE => {
+                expect_payload_len(payload_len, 0)?;
+                Message::Choke
+            }
+            MESSAGE_UNCHOKE => {
+                expect_payload_len(payload_len, 0)?;
+                Message::UnChoke
+            }
+            MESSAGE_INTERESTED => {
+                expect_payload_len(payload_len, 0)?;
+                Message::Intersted
+            }
+            MESSAGE_NOT_INTERESTED => {
+                expect_payload_len(payload_len, 0)?;
+                Message::NotInterested
+            }
+            MESSAGE_HAVE => {
+                expect_payload_len(payload_len, 4)?;
+                Message::Have {
+                    piece_index: frame.get_u32() as usize,
+                }
+            }
+            MESSAGE_BITFIELD => Message::BitField {
+                bitfield: BitField::from_raw(&frame),
+            },
+            MESSAGE_REQUEST => {
+                expect_payload_len(payload_len, 12)?;
+                Message::Request {
+                    piece_index: frame.get_u32() as usize,
+                    block_begin: frame.get_u32() as usize,
+                    block_length: frame.get_u32() as usize,
+                }
+            }
+            MESSAGE_PIECE => {
+                // the block is whatever remains of the frame after index and begin,
+                // so it can never run past the declared length
+                if payload_len < 8 {
+                    return Err(Error::MessageEndUnexpected);
+                }
+                Message::Piece {
+                    piece_index: frame.get_u32() as usize,
+                    block_begin: frame.get_u32() as usize,
+                    block_data: frame.freeze(),
+                }
+            }
+            MESSAGE_CANCEL => {
+                expect_payload_len(payload_len, 12)?;
+                Message::Cancel {
+                    piece_index: frame.get_u32() as usize,
+                    block_begin: frame.get_u32() as usize,
+                    block_length: frame.get_u32() as usize,
+                }
+            }
+            MESSAGE_PORT => {
+                expect_payload_len(payload_len, 2)?;
+                Message::Port {
+                    port: frame.get_u16(),
+                }
+            }
+            MESSAGE_SUGGEST_PIECE => {
+                expect_payload_len(payload_len, 4)?;
+                Message::SuggestPiece {
+                    piece_index: frame.get_u32() as usize,
+                }
+            }
+            MESSAGE_HAVE_ALL => {
+                expect_payload_len(payload_len, 0)?;
+                Message::HaveAll
+            }
+            MESSAGE_HAVE_NONE => {
+                expect_payload_len(payload_len, 0)?;
+                Message::HaveNone
+            }
+            MESSAGE_REJECT_REQUEST => {
+                expect_payload_len(payload_len, 12)?;
+                Message::RejectRequest {
+                    piece_index: frame.get_u32() as usize,
+                    block_begin: frame.get_u32() as usize,
+                    block_length: frame.get_u32() as usize,
+                }
+            }
+            MESSAGE_ALLOWED_FAST => {
+                expect_payload_len(payload_len, 4)?;
+                Message::AllowedFast {
+                    piece_index: frame.get_u32() as usize,
+                }
+            }
+            MESSAGE_EXTENDED => {
+                if payload_len < 1 {
+                    return Err(Error::MessageEndUnexpected);
+                }
+                Message::Extended {
+                    extended_id: frame.get_u8(),
+                    payload: frame.freeze(),
+                }
+            }
+            id => return Err(Error::MessageTypeNotSupport(id)),
+        };
+        Ok(Some(message))
+    }
+}
+
+/// Check the payload length of a fixed size message.
+fn expect_payload_len(payload_len: usize, expected: usize) -> Result<(), Error> {
+    match payload_len.cmp(&expected) {
+        Ordering::Less => Err(Error::MessageEndUnexpected),
+        Ordering::Greater => Err(Error::WrongMessageLength),
+        Ordering::Equal => Ok(()),
     }
 }
 
 /// HandshakeMessage represents the handshake message used by the peer protocol.
+#[derive(Debug, PartialEq, Eq)]
 pub struct HandshakeMessage {
     /// string identifier of the protocol, must be "BitTorrent protocol"
     pub pstr: [u8; 19],
@@ -86,13 +355,36 @@ pub struct HandshakeMessage {
 
 impl HandshakeMessage {
     pub fn new(info_hash: HashPiece, peer_id: HashPiece) -> Self {
+        let mut reserved = [0; 8];
+        reserved[RESERVED_EXTENSION_PROTOCOL.0] |= RESERVED_EXTENSION_PROTOCOL.1;
+        reserved[RESERVED_FAST_EXTENSION.0] |= RESERVED_FAST_EXTENSION.1;
         HandshakeMessage {
             pstr: *b"BitTorrent protocol",
-            reserved: [0; 8],
+            reserved,
             info_hash,
             peer_id,
         }
     }
+
+    /// Does the sender support the extension protocol?
+    pub fn supports_extension_protocol(&self) -> bool {
+        self.reserved[RESERVED_EXTENSION_PROTOCOL.0] & RESERVED_EXTENSION_PROTOCOL.1 != 0
+    }
+
+    /// Does the sender support the fast extension?
+    pub fn supports_fast_extension(&self) -> bool {
+        self.reserved[RESERVED_FAST_EXTENSION.0] & RESERVED_FAST_EXTENSION.1 != 0
+    }
+
+    /// Advertise that the sender runs a DHT node.
+    pub fn set_dht(&mut self) {
+        self.reserved[RESERVED_DHT.0] |= RESERVED_DHT.1;
+    }
+
+    /// Does the sender run a DHT node?
+    pub fn supports_dht(&self) -> bool {
+        self.reserved[RESERVED_DHT.0] & RESERVED_DHT.1 != 0
+    }
 }
 
 pub(crate) struct HandshakeMessageCodec;
@@ -106,7 +398,13 @@ impl Encoder for HandshakeMessageCodec {
         item: Self::Item,
         dst: &mut asynchronous_codec::BytesMut,
     ) -> Result<(), Self::Error> {
-        todo!()
+        dst.reserve(HANDSHAKE_LEN);
+        dst.put_u8(item.pstr.len() as u8);
+        dst.put_slice(&item.pstr);
+        dst.put_slice(&item.reserved);
+        dst.put_slice(item.info_hash.as_ref());
+        dst.put_slice(item.peer_id.as_ref());
+        Ok(())
     }
 }
 
@@ -118,6 +416,206 @@ impl Decoder for HandshakeMessageCodec {
         &mut self,
         src: &mut asynchronous_codec::BytesMut,
     ) -> Result<Option<Self::Item>, Self::Error> {
-        todo!()
+        if src.len() < HANDSHAKE_LEN {
+            return Ok(None);
+        }
+        let mut message = HandshakeMessage::new(HashPiece::default(), HashPiece::default());
+        if src.get_u8() as usize != message.pstr.len() {
+            return Err(Error::InvalidProtocol);
+        }
+        let mut pstr = [0; 19];
+        src.copy_to_slice(&mut pstr);
+        if pstr != message.pstr {
+            return Err(Error::InvalidProtocol);
+        }
+        src.copy_to_slice(&mut message.reserved);
+        src.copy_to_slice(message.info_hash.as_mut());
+        src.copy_to_slice(message.peer_id.as_mut());
+        Ok(Some(message))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use asynchronous_codec::BytesMut;
+
+    /// Feed `input` to the decoder `chunk` bytes at a time and collect every message.
+    fn decode_chunked(input: &[u8], chunk: usize) -> Vec<Message> {
+        let mut codec = MessageCodec;
+        let mut buf = BytesMut::new();
+        let mut messages = Vec::new();
+        for part in input.chunks(chunk) {
+            buf.extend_from_slice(part);
+            while let Some(message) = codec.decode(&mut buf).unwrap() {
+                messages.push(message);
+            }
+        }
+        assert!(buf.is_empty());
+        messages
+    }
+
+    #[test]
+    fn test_decode_messages() {
+        let mut input = Vec::new();
+        input.extend_from_slice(&[0, 0, 0, 0]);
+        input.extend_from_slice(&[0, 0, 0, 1, 1]);
+        input.extend_from_slice(&[0, 0, 0, 5, 4, 0, 0, 0, 7]);
+        input.extend_from_slice(&[0, 0, 0, 3, 5, 0b0000_0101, 0xff]);
+        input.extend_from_slice(&[0, 0, 0, 13, 6, 0, 0, 0, 1, 0, 0, 0x40, 0, 0, 0, 0x40, 0]);
+        input.extend_from_slice(&[0, 0, 0, 12, 7, 0, 0, 0, 1, 0, 0, 0, 0, b'a', b'b', b'c']);
+        input.extend_from_slice(&[0, 0, 0, 3, 9, 0x1a, 0xe1]);
+        let expected = vec![
+            Message::KeepAlive,
+            Message::UnChoke,
+            Message::Have { piece_index: 7 },
+            Message::BitField {
+                bitfield: BitField::from_raw(&[0b0000_0101, 0xff]),
+            },
+            Message::Request {
+                piece_index: 1,
+                block_begin: 0x4000,
+                block_length: 0x4000,
+            },
+            Message::Piece {
+                piece_index: 1,
+                block_begin: 0,
+                block_data: Bytes::from_static(b"abc"),
+            },
+            Message::Port { port: 6881 },
+        ];
+        for chunk in [1, 2, 3, 7, input.len()] {
+            assert_eq!(decode_chunked(&input, chunk), expected);
+        }
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        let messages = vec![
+            Message::KeepAlive,
+            Message::Choke,
+            Message::UnChoke,
+            Message::Intersted,
+            Message::NotInterested,
+            Message::Have { piece_index: 3 },
+            Message::BitField {
+                bitfield: BitField::from_raw(&[0b1010_0000]),
+            },
+            Message::Request {
+                piece_index: 1,
+                block_begin: 0x4000,
+                block_length: 0x4000,
+            },
+            Message::Piece {
+                piece_index: 1,
+                block_begin: 0x4000,
+                block_data: Bytes::from_static(b"hello"),
+            },
+            Message::Cancel {
+                piece_index: 1,
+                block_begin: 0x4000,
+                block_length: 0x4000,
+            },
+            Message::Port { port: 6881 },
+            Message::Extended {
+                extended_id: 0,
+                payload: Bytes::from_static(b"d1:mdee"),
+            },
+        ];
+        let mut codec = MessageCodec;
+        let mut buf = BytesMut::new();
+        for message in messages.iter() {
+            codec.encode(message.clone(), &mut buf).unwrap();
+        }
+        assert_eq!(decode_chunked(&buf, 5), messages);
+    }
+
+    #[test]
+    fn test_fast_extension_messages() {
+        let cases = vec![
+            (
+                Message::SuggestPiece { piece_index: 5 },
+                vec![0, 0, 0, 5, 0x0D, 0, 0, 0, 5],
+            ),
+            (Message::HaveAll, vec![0, 0, 0, 1, 0x0E]),
+            (Message::HaveNone, vec![0, 0, 0, 1, 0x0F]),
+            (
+                Message::RejectRequest {
+                    piece_index: 1,
+                    block_begin: 0x4000,
+                    block_length: 0x4000,
+                },
+                vec![0, 0, 0, 13, 0x10, 0, 0, 0, 1, 0, 0, 0x40, 0, 0, 0, 0x40, 0],
+            ),
+            (
+                Message::AllowedFast { piece_index: 9 },
+                vec![0, 0, 0, 5, 0x11, 0, 0, 0, 9],
+            ),
+        ];
+        for (message, bytes) in cases {
+            let mut buf = BytesMut::new();
+            MessageCodec.encode(message.clone(), &mut buf).unwrap();
+            assert_eq!(&buf[..], &bytes[..]);
+            assert_eq!(decode_chunked(&bytes, 3), vec![message]);
+        }
+    }
+
+    #[test]
+    fn test_handshake() {
+        let message = HandshakeMessage::new(HashPiece::rand_new(), HashPiece::rand_new());
+        let mut buf = BytesMut::new();
+        HandshakeMessageCodec
+            .encode(
+                HandshakeMessage::new(message.info_hash.clone(), message.peer_id.clone()),
+                &mut buf,
+            )
+            .unwrap();
+        assert_eq!(buf.len(), HANDSHAKE_LEN);
+        assert_eq!(&buf[..20], &b"\x13BitTorrent protocol"[..]);
+        assert_eq!(&buf[20..28], &[0, 0, 0, 0, 0, 0x10, 0, 0x04]);
+        assert!(!message.supports_dht());
+        let mut partial = buf.split_to(HANDSHAKE_LEN - 1);
+        assert!(HandshakeMessageCodec
+            .decode(&mut partial)
+            .unwrap()
+            .is_none());
+        partial.unsplit(buf);
+        assert_eq!(
+            HandshakeMessageCodec.decode(&mut partial).unwrap(),
+            Some(message)
+        );
+    }
+
+    #[test]
+    fn test_decode_partial_frame() {
+        let mut codec = MessageCodec;
+        let mut buf = BytesMut::from(&[0u8, 0, 0, 5, 4, 0, 0][..]);
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+        assert_eq!(buf.len(), 7);
+        buf.extend_from_slice(&[0, 2]);
+        assert_eq!(
+            codec.decode(&mut buf).unwrap(),
+            Some(Message::Have { piece_index: 2 })
+        );
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        let mut codec = MessageCodec;
+        let mut buf = BytesMut::from(&[0u8, 0, 0, 1, 42][..]);
+        assert!(matches!(
+            codec.decode(&mut buf),
+            Err(Error::MessageTypeNotSupport(42))
+        ));
+        let mut buf = BytesMut::from(&[0u8, 0, 0, 6, 4, 0, 0, 0, 1, 0][..]);
+        assert!(matches!(
+            codec.decode(&mut buf),
+            Err(Error::WrongMessageLength)
+        ));
+        let mut buf = BytesMut::from(&[0u8, 0, 0, 5, 7, 0, 0, 0, 1][..]);
+        assert!(matches!(
+            codec.decode(&mut buf),
+            Err(Error::MessageEndUnexpected)
+        ));
     }
 }
@@ -0,0 +1,239 @@
+//! This module implements the metadata exchange extension defined in https://www.bittorrent.org/beps/bep_0009.html
+
+use super::error::{Error, Result};
+use crate::metainfo::{HashPiece, Info};
+use bytes::{BufMut, Bytes, BytesMut};
+use serde::{Deserialize, Serialize};
+use serde_bencode::{
+    de::{from_bytes, Deserializer},
+    ser::to_bytes,
+};
+
+/// Name of the extension in the `m` dictionary of the extended handshake.
+pub(crate) const UT_METADATA: &str = "ut_metadata";
+/// Extended message id local expects for ut_metadata messages.
+pub(crate) const LOCAL_UT_METADATA_ID: u8 = 1;
+/// Metadata is transferred in pieces of 16KiB, the last one may be smaller.
+pub(crate) const METADATA_PIECE_LEN: usize = 16 * 1024;
+/// Largest metadata size accepted from a remote peer, real info dictionaries are far smaller.
+pub(crate) const MAX_METADATA_SIZE: usize = 8 * 1024 * 1024;
+
+const MSG_TYPE_REQUEST: u8 = 0;
+const MSG_TYPE_DATA: u8 = 1;
+const MSG_TYPE_REJECT: u8 = 2;
+
+/// Bencoded dictionary at the head of every ut_metadata message.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+struct MetadataHeader {
+    msg_type: u8,
+    piece: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    total_size: Option<usize>,
+}
+
+/// MetadataMessage is the payload of an extended message of ut_metadata.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum MetadataMessage {
+    Request {
+        piece: usize,
+    },
+    /// The piece data is appended to the bencoded dictionary
+    Data {
+        piece: usize,
+        total_size: usize,
+        data: Bytes,
+    },
+    Reject {
+        piece: usize,
+    },
+}
+
+impl MetadataMessage {
+    pub fn from_payload(payload: &[u8]) -> Result<Self> {
+        // the deserializer reads byte by byte, so `rest` is left at the end of the dictionary
+        let mut rest = payload;
+        let header: MetadataHeader = Deserialize::deserialize(&mut Deserializer::new(&mut rest))?;
+        match header.msg_type {
+            MSG_TYPE_REQUEST => Ok(MetadataMessage::Request {
+                piece: header.piece,
+            }),
+            MSG_TYPE_DATA => Ok(MetadataMessage::Data {
+                piece: header.piece,
+                total_size: header.total_size.ok_or(Error::InvalidMetadataMessage)?,
+                data: Bytes::copy_from_slice(rest),
+            }),
+            MSG_TYPE_REJECT => Ok(MetadataMessage::Reject {
+                piece: header.piece,
+            }),
+            _ => Err(Error::InvalidMetadataMessage),
+        }
+    }
+
+    pub fn to_payload(&self) -> Result<Bytes> {
+        let (header, data) = match self {
+            MetadataMessage::Request { piece } => (
+                MetadataHeader {
+                    msg_type: MSG_TYPE_REQUEST,
+                    piece: *piece,
+                    total_size: None,
+                },
+                None,
+            ),
+            MetadataMessage::Data {
+                piece,
+                total_size,
+                data,
+            } => (
+                MetadataHeader {
+                    msg_type: MSG_TYPE_DATA,
+                    piece: *piece,
+                    total_size: Some(*total_size),
+                },
+                Some(data),
+            ),
+            MetadataMessage::Reject { piece } => (
+                MetadataHeader {
+                    msg_type: MSG_TYPE_REJECT,
+                    piece: *piece,
+                    total_size: None,
+                },
+                None,
+            ),
+        };
+        let mut buf = BytesMut::from(&to_bytes(&header)?[..]);
+        if let Some(data) = data {
+            buf.put_slice(data);
+        }
+        Ok(buf.freeze())
+    }
+}
+
+/// MetadataAssembler collects the metadata pieces and verifies them against the info hash.
+#[derive(Debug)]
+pub(crate) struct MetadataAssembler {
+    total_size: usize,
+    pieces: Vec<Option<Bytes>>,
+}
+
+impl MetadataAssembler {
+    /// The size is announced by the remote peer, so it is checked before anything is allocated.
+    pub(crate) fn new(total_size: usize) -> Result<Self> {
+        if total_size == 0 || total_size > MAX_METADATA_SIZE {
+            return Err(Error::InvalidMetadataSize(total_size));
+        }
+        let count = (total_size + METADATA_PIECE_LEN - 1) / METADATA_PIECE_LEN;
+        Ok(Self {
+            total_size,
+            pieces: vec![None; count],
+        })
+    }
+
+    pub(crate) fn piece_count(&self) -> usize {
+        self.pieces.len()
+    }
+
+    /// Store a received piece, checking it has the expected length.
+    pub(crate) fn insert(&mut self, piece: usize, data: Bytes) -> Result<()> {
+        if piece >= self.pieces.len() {
+            return Err(Error::InvalidMetadataMessage);
+        }
+        let expected_len = if piece + 1 == self.pieces.len() {
+            self.total_size - piece * METADATA_PIECE_LEN
+        } else {
+            METADATA_PIECE_LEN
+        };
+        if data.len() != expected_len {
+            return Err(Error::InvalidMetadataMessage);
+        }
+        self.pieces[piece] = Some(data);
+        Ok(())
+    }
+
+    pub(crate) fn is_complete(&self) -> bool {
+        self.pieces.iter().all(Option::is_some)
+    }
+
+    /// Concatenate the pieces, check the SHA1 against `info_hash` and parse the info dictionary.
+    pub(crate) fn finish(self, info_hash: &HashPiece) -> Result<Info> {
+        let mut buf = Vec::with_capacity(self.total_size);
+        for piece in self.pieces {
+            buf.extend_from_slice(&piece.ok_or(Error::InvalidMetadataMessage)?);
+        }
+        if &HashPiece::from(buf.as_slice()) != info_hash {
+            return Err(Error::MetadataHashMismatch);
+        }
+        let info: Info = from_bytes(&buf)?;
+        info.validate()?;
+        Ok(info)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metadata_message() {
+        let request = MetadataMessage::Request { piece: 0 };
+        assert_eq!(
+            request.to_payload().unwrap(),
+            Bytes::from_static(b"d8:msg_typei0e5:piecei0ee")
+        );
+        let data =
+            MetadataMessage::from_payload(b"d8:msg_typei1e5:piecei1e10:total_sizei16388eexxxx")
+                .unwrap();
+        assert_eq!(
+            data,
+            MetadataMessage::Data {
+                piece: 1,
+                total_size: 16388,
+                data: Bytes::from_static(b"xxxx"),
+            }
+        );
+        assert_eq!(
+            MetadataMessage::from_payload(&data.to_payload().unwrap()).unwrap(),
+            data
+        );
+        assert_eq!(
+            MetadataMessage::from_payload(b"d8:msg_typei2e5:piecei3ee").unwrap(),
+            MetadataMessage::Reject { piece: 3 }
+        );
+        assert!(MetadataMessage::from_payload(b"d8:msg_typei1e5:piecei0ee").is_err());
+    }
+
+    #[test]
+    fn test_metadata_assembler() {
+        for total_size in [0, MAX_METADATA_SIZE + 1, usize::MAX] {
+            assert!(matches!(
+                MetadataAssembler::new(total_size),
+                Err(Error::InvalidMetadataSize(_))
+            ));
+        }
+        let mut assembler = MetadataAssembler::new(METADATA_PIECE_LEN + 4).unwrap();
+        assert_eq!(assembler.piece_count(), 2);
+        assert!(assembler.insert(1, Bytes::from_static(b"xx")).is_err());
+        assembler.insert(1, Bytes::from_static(b"xxxx")).unwrap();
+        assert!(!assembler.is_complete());
+        assembler
+            .insert(0, Bytes::from(vec![b'x'; METADATA_PIECE_LEN]))
+            .unwrap();
+        assert!(assembler.is_complete());
+        assert!(matches!(
+            assembler.finish(&HashPiece::default()),
+            Err(Error::MetadataHashMismatch)
+        ));
+
+        // the info dictionary has both length and files
+        let metadata = b"d5:filesld6:lengthi6e4:pathl5:1.txteee\
+            6:lengthi6e4:name3:dir12:piece lengthi524288e6:pieces0:e";
+        let mut assembler = MetadataAssembler::new(metadata.len()).unwrap();
+        assembler
+            .insert(0, Bytes::from_static(&metadata[..]))
+            .unwrap();
+        assert!(matches!(
+            assembler.finish(&HashPiece::from(&metadata[..])),
+            Err(Error::MetaInfo(crate::metainfo::Error::AmbiguousLength))
+        ));
+    }
+}
@@ -1,3 +1,40 @@
+//! This module implements the peer wire protocol defined in https://www.bittorrent.org/beps/bep_0003.html
+
+mod bitfield;
+pub use bitfield::BitField;
+
+mod choke;
+pub use choke::{ChokeManager, RECHOKE_INTERVAL};
+
+mod download;
+pub use download::DownloadCoordinator;
+
 mod error;
+pub use error::{Error, Result};
+
+mod extension;
+pub use extension::ExtendedHandshake;
+
 mod message;
+pub use message::{HandshakeMessage, Message};
+
+mod metadata;
+pub use metadata::MetadataMessage;
+
+mod mse;
+pub use mse::{accept, connect, initiate, CryptoMode, EncryptedStream, EncryptionPolicy};
+
+mod pex;
+pub use pex::{PexMessage, PexPeer};
+
+mod picker;
+pub use picker::{PiecePicker, BLOCK_LEN};
+
+mod piece_buffer;
+pub use piece_buffer::PieceBuffer;
+
+mod rate;
+pub use rate::Rate;
+
 mod session;
+pub use session::{Command, Session, SessionEvent};
@@ -0,0 +1,624 @@
+//! This module implements Message Stream Encryption (MSE/PE), the obfuscated handshake most clients
+//! use to hide BitTorrent traffic from throttling, see https://wiki.vuze.com/w/Message_Stream_Encryption
+
+use super::error::{Error, Result};
+use crate::metainfo::HashPiece;
+use async_std::io::{prelude::*, Read, Write};
+use futures::ready;
+use num_bigint::BigUint;
+use rand::{random, thread_rng, Rng};
+use sha1::{Digest, Sha1};
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// The 768 bit prime of the Diffie-Hellman key exchange, the generator is 2.
+const PRIME: &[u8] = b"FFFFFFFFFFFFFFFFC90FDAA22168C234C4C6628B80DC1CD129024E088A67CC74\
+                       020BBEA63B139B22514A08798E3404DDEF9519B3CD3A431B302B0A6DF25F1437\
+                       4FE1356D6D51C245E485B576625E7EC6F44C42E9A63A36210000000000090563";
+const GENERATOR: u32 = 2;
+/// Length of the public keys and the shared secret.
+const KEY_LEN: usize = 96;
+/// Length of the random private keys.
+const PRIVATE_KEY_LEN: usize = 20;
+/// Maximum length of every random padding.
+const MAX_PAD_LEN: usize = 512;
+/// Verification constant, eight zero bytes.
+const VC: [u8; 8] = [0; 8];
+/// Number of keystream bytes discarded before RC4 is used.
+const RC4_DISCARD_LEN: usize = 1024;
+/// Start of a plaintext handshake, a receiver falls back to plaintext when it sees it.
+const PLAINTEXT_PREFIX: &[u8] = b"\x13BitTorrent protocol";
+
+const CRYPTO_PLAINTEXT: u32 = 0x01;
+const CRYPTO_RC4: u32 = 0x02;
+
+/// EncryptionPolicy decides which crypto methods are offered and accepted.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum EncryptionPolicy {
+    /// Prefer RC4 but allow plaintext, including peers that skip the MSE handshake
+    #[default]
+    Prefer,
+    /// Only RC4 is acceptable
+    Require,
+}
+
+impl EncryptionPolicy {
+    fn crypto_provide(self) -> u32 {
+        match self {
+            EncryptionPolicy::Prefer => CRYPTO_PLAINTEXT | CRYPTO_RC4,
+            EncryptionPolicy::Require => CRYPTO_RC4,
+        }
+    }
+
+    /// Pick a method out of the ones provided by the remote peer.
+    fn select(self, crypto_provide: u32) -> Option<CryptoMode> {
+        let common = self.crypto_provide() & crypto_provide;
+        if common & CRYPTO_RC4 != 0 {
+            Some(CryptoMode::Rc4)
+        } else if common & CRYPTO_PLAINTEXT != 0 {
+            Some(CryptoMode::Plaintext)
+        } else {
+            None
+        }
+    }
+}
+
+/// CryptoMode is the method negotiated for the payload stream.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum CryptoMode {
+    Plaintext,
+    Rc4,
+}
+
+impl CryptoMode {
+    fn crypto_select(self) -> u32 {
+        match self {
+            CryptoMode::Plaintext => CRYPTO_PLAINTEXT,
+            CryptoMode::Rc4 => CRYPTO_RC4,
+        }
+    }
+}
+
+/// Rc4 is the stream cipher used by MSE.
+struct Rc4 {
+    state: [u8; 256],
+    i: u8,
+    j: u8,
+}
+
+impl Rc4 {
+    fn new(key: &[u8]) -> Self {
+        let mut state = [0u8; 256];
+        for (i, byte) in state.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        let mut j = 0u8;
+        for i in 0..256 {
+            j = j.wrapping_add(state[i]).wrapping_add(key[i % key.len()]);
+            state.swap(i, j as usize);
+        }
+        Self { state, i: 0, j: 0 }
+    }
+
+    /// Create the cipher keyed with HASH(`name`, S, SKEY), the first 1024 bytes are discarded.
+    fn for_key(name: &[u8], secret: &[u8], info_hash: &HashPiece) -> Self {
+        let mut rc4 = Rc4::new(&hash(&[name, secret, info_hash.as_ref()]));
+        rc4.apply(&mut [0; RC4_DISCARD_LEN]);
+        rc4
+    }
+
+    /// Encrypt or decrypt `buf` in place.
+    fn apply(&mut self, buf: &mut [u8]) {
+        for byte in buf.iter_mut() {
+            self.i = self.i.wrapping_add(1);
+            self.j = self.j.wrapping_add(self.state[self.i as usize]);
+            self.state.swap(self.i as usize, self.j as usize);
+            let k = self.state
+                [self.state[self.i as usize].wrapping_add(self.state[self.j as usize]) as usize];
+            *byte ^= k;
+        }
+    }
+}
+
+/// KeyPair is one side of the Diffie-Hellman key exchange.
+struct KeyPair {
+    private: BigUint,
+    public: [u8; KEY_LEN],
+}
+
+impl KeyPair {
+    fn new() -> Self {
+        let private = BigUint::from_bytes_be(&random::<[u8; PRIVATE_KEY_LEN]>());
+        let public = BigUint::from(GENERATOR).modpow(&private, &prime());
+        Self {
+            private,
+            public: to_key_bytes(&public),
+        }
+    }
+
+    /// Compute the shared secret S from the public key of the remote peer.
+    fn secret(&self, remote: &[u8; KEY_LEN]) -> Result<[u8; KEY_LEN]> {
+        let prime = prime();
+        let remote = BigUint::from_bytes_be(remote);
+        if remote <= BigUint::from(1u32) || remote >= &prime - 1u32 {
+            return Err(Error::EncryptionHandshake("invalid public key"));
+        }
+        Ok(to_key_bytes(&remote.modpow(&self.private, &prime)))
+    }
+}
+
+fn prime() -> BigUint {
+    BigUint::parse_bytes(PRIME, 16).unwrap()
+}
+
+fn to_key_bytes(n: &BigUint) -> [u8; KEY_LEN] {
+    let bytes = n.to_bytes_be();
+    let mut key = [0u8; KEY_LEN];
+    key[KEY_LEN - bytes.len()..].copy_from_slice(&bytes);
+    key
+}
+
+fn hash(parts: &[&[u8]]) -> [u8; 20] {
+    let mut hasher = Sha1::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize().into()
+}
+
+/// HASH('req2', SKEY) xor HASH('req3', S), which lets the receiver identify the torrent.
+fn obfuscated_info_hash(secret: &[u8], info_hash: &HashPiece) -> [u8; 20] {
+    let mut req2 = hash(&[b"req2", info_hash.as_ref()]);
+    for (byte, mask) in req2.iter_mut().zip(hash(&[b"req3", secret]).iter()) {
+        *byte ^= mask;
+    }
+    req2
+}
+
+fn random_pad() -> Vec<u8> {
+    let len = thread_rng().gen_range(0..=MAX_PAD_LEN);
+    (0..len).map(|_| random()).collect()
+}
+
+/// Append VC, `crypto`, len(pad) and pad.
+fn put_crypto_header(buf: &mut Vec<u8>, crypto: u32) {
+    let pad = random_pad();
+    buf.extend_from_slice(&VC);
+    buf.extend_from_slice(&crypto.to_be_bytes());
+    buf.extend_from_slice(&(pad.len() as u16).to_be_bytes());
+    buf.extend(pad);
+}
+
+/// Read the decrypted VC, crypto field and padding, returning the crypto field.
+async fn read_crypto_header<C: Read + Unpin>(
+    conn: &mut C,
+    decryptor: &mut Rc4,
+    check_vc: bool,
+) -> Result<u32> {
+    let mut header = [0u8; 14];
+    let header = if check_vc {
+        &mut header[..]
+    } else {
+        // the VC was consumed while synchronizing
+        &mut header[VC.len()..]
+    };
+    conn.read_exact(header).await?;
+    decryptor.apply(header);
+    let (vc, header) = header.split_at(header.len() - 6);
+    if vc != &VC[..vc.len()] {
+        return Err(Error::EncryptionHandshake("invalid verification constant"));
+    }
+    let crypto = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+    let pad_len = u16::from_be_bytes([header[4], header[5]]) as usize;
+    if pad_len > MAX_PAD_LEN {
+        return Err(Error::EncryptionHandshake("padding too long"));
+    }
+    let mut pad = vec![0u8; pad_len];
+    conn.read_exact(&mut pad).await?;
+    decryptor.apply(&mut pad);
+    Ok(crypto)
+}
+
+/// Skip the random padding of the remote peer until `pattern` is read.
+async fn synchronize<C: Read + Unpin>(conn: &mut C, pattern: &[u8]) -> Result<()> {
+    let mut window = Vec::with_capacity(MAX_PAD_LEN + pattern.len());
+    let mut byte = [0u8; 1];
+    while window.len() < MAX_PAD_LEN + pattern.len() {
+        conn.read_exact(&mut byte).await?;
+        window.push(byte[0]);
+        if window.ends_with(pattern) {
+            return Ok(());
+        }
+    }
+    Err(Error::EncryptionHandshake(
+        "synchronization pattern not found",
+    ))
+}
+
+/// Open a connection with `open` and run the MSE handshake as the connecting side.
+/// With `EncryptionPolicy::Prefer` a remote peer failing the handshake, usually because it doesn't
+/// know MSE and dropped the connection, is connected to again without encryption,
+/// the plaintext BitTorrent handshake is then sent on the returned stream.
+pub async fn connect<C, F, Fut>(
+    mut open: F,
+    info_hash: &HashPiece,
+    policy: EncryptionPolicy,
+) -> Result<EncryptedStream<C>>
+where
+    C: Read + Write + Unpin,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = io::Result<C>>,
+{
+    match initiate(open().await?, info_hash, policy).await {
+        Err(_) if policy == EncryptionPolicy::Prefer => {
+            Ok(EncryptedStream::plaintext(open().await?, Vec::new()))
+        }
+        result => result,
+    }
+}
+
+/// Run the MSE handshake as the connecting side for the torrent of `info_hash`.
+pub async fn initiate<C: Read + Write + Unpin>(
+    mut conn: C,
+    info_hash: &HashPiece,
+    policy: EncryptionPolicy,
+) -> Result<EncryptedStream<C>> {
+    // 1 A->B: Diffie Hellman Ya, PadA
+    let key_pair = KeyPair::new();
+    let mut buf = key_pair.public.to_vec();
+    buf.extend(random_pad());
+    conn.write_all(&buf).await?;
+    // 2 B->A: Diffie Hellman Yb, PadB
+    let mut remote = [0u8; KEY_LEN];
+    conn.read_exact(&mut remote).await?;
+    let secret = key_pair.secret(&remote)?;
+    let mut encryptor = Rc4::for_key(b"keyA", &secret, info_hash);
+    let mut decryptor = Rc4::for_key(b"keyB", &secret, info_hash);
+    // 3 A->B: HASH('req1', S), HASH('req2', SKEY) xor HASH('req3', S),
+    // ENCRYPT(VC, crypto_provide, len(PadC), PadC, len(IA)), ENCRYPT(IA)
+    let mut buf = hash(&[b"req1", &secret]).to_vec();
+    buf.extend_from_slice(&obfuscated_info_hash(&secret, info_hash));
+    let mut payload = Vec::new();
+    put_crypto_header(&mut payload, policy.crypto_provide());
+    // no initial payload, the BitTorrent handshake follows the MSE handshake
+    payload.extend_from_slice(&0u16.to_be_bytes());
+    encryptor.apply(&mut payload);
+    buf.extend(payload);
+    conn.write_all(&buf).await?;
+    // 4 B->A: ENCRYPT(VC, crypto_select, len(padD), padD)
+    let mut vc = VC;
+    decryptor.apply(&mut vc);
+    synchronize(&mut conn, &vc).await?;
+    let crypto_select = read_crypto_header(&mut conn, &mut decryptor, false).await?;
+    let mode = match crypto_select {
+        CRYPTO_RC4 => CryptoMode::Rc4,
+        CRYPTO_PLAINTEXT => CryptoMode::Plaintext,
+        _ => return Err(Error::EncryptionHandshake("invalid crypto_select")),
+    };
+    if policy.crypto_provide() & crypto_select == 0 {
+        return Err(Error::EncryptionHandshake("crypto_select not provided"));
+    }
+    Ok(EncryptedStream::new(
+        conn,
+        mode,
+        encryptor,
+        decryptor,
+        Vec::new(),
+    ))
+}
+
+/// Run the MSE handshake as the accepting side for the torrent of `info_hash`.
+/// With `EncryptionPolicy::Prefer` a peer that starts with a plaintext handshake is accepted as is.
+pub async fn accept<C: Read + Write + Unpin>(
+    mut conn: C,
+    info_hash: &HashPiece,
+    policy: EncryptionPolicy,
+) -> Result<EncryptedStream<C>> {
+    // 1 A->B: Diffie Hellman Ya, PadA
+    let mut remote = [0u8; KEY_LEN];
+    conn.read_exact(&mut remote[..PLAINTEXT_PREFIX.len()])
+        .await?;
+    if remote.starts_with(PLAINTEXT_PREFIX) {
+        if policy == EncryptionPolicy::Require {
+            return Err(Error::EncryptionHandshake("remote peer doesn't encrypt"));
+        }
+        return Ok(EncryptedStream::plaintext(conn, PLAINTEXT_PREFIX.to_vec()));
+    }
+    conn.read_exact(&mut remote[PLAINTEXT_PREFIX.len()..])
+        .await?;
+    // 2 B->A: Diffie Hellman Yb, PadB
+    let key_pair = KeyPair::new();
+    let mut buf = key_pair.public.to_vec();
+    buf.extend(random_pad());
+    conn.write_all(&buf).await?;
+    let secret = key_pair.secret(&remote)?;
+    // 3 A->B: HASH('req1', S), HASH('req2', SKEY) xor HASH('req3', S),
+    // ENCRYPT(VC, crypto_provide, len(PadC), PadC, len(IA)), ENCRYPT(IA)
+    synchronize(&mut conn, &hash(&[b"req1", &secret])).await?;
+    let mut skey = [0u8; 20];
+    conn.read_exact(&mut skey).await?;
+    if skey != obfuscated_info_hash(&secret, info_hash) {
+        return Err(Error::InvaildInfoHash);
+    }
+    let mut encryptor = Rc4::for_key(b"keyB", &secret, info_hash);
+    let mut decryptor = Rc4::for_key(b"keyA", &secret, info_hash);
+    let crypto_provide = read_crypto_header(&mut conn, &mut decryptor, true).await?;
+    let mut ia_len = [0u8; 2];
+    conn.read_exact(&mut ia_len).await?;
+    decryptor.apply(&mut ia_len);
+    let mut initial_payload = vec![0u8; u16::from_be_bytes(ia_len) as usize];
+    conn.read_exact(&mut initial_payload).await?;
+    decryptor.apply(&mut initial_payload);
+    let mode = policy
+        .select(crypto_provide)
+        .ok_or(Error::EncryptionHandshake("no common crypto method"))?;
+    // 4 B->A: ENCRYPT(VC, crypto_select, len(padD), padD)
+    let mut buf = Vec::new();
+    put_crypto_header(&mut buf, mode.crypto_select());
+    encryptor.apply(&mut buf);
+    conn.write_all(&buf).await?;
+    Ok(EncryptedStream::new(
+        conn,
+        mode,
+        encryptor,
+        decryptor,
+        initial_payload,
+    ))
+}
+
+/// EncryptedStream wraps a connection after the MSE handshake,
+/// the payload is encrypted or sent as is depending on the negotiated mode.
+pub struct EncryptedStream<C> {
+    inner: C,
+    mode: CryptoMode,
+    encryptor: Option<Rc4>,
+    decryptor: Option<Rc4>,
+    /// decrypted bytes received during the handshake and not read yet
+    read_buf: Vec<u8>,
+    /// encrypted bytes not written to the connection yet
+    write_buf: Vec<u8>,
+}
+
+impl<C> EncryptedStream<C> {
+    fn new(inner: C, mode: CryptoMode, encryptor: Rc4, decryptor: Rc4, read_buf: Vec<u8>) -> Self {
+        match mode {
+            CryptoMode::Plaintext => Self::plaintext(inner, read_buf),
+            CryptoMode::Rc4 => Self {
+                inner,
+                mode,
+                encryptor: Some(encryptor),
+                decryptor: Some(decryptor),
+                read_buf,
+                write_buf: Vec::new(),
+            },
+        }
+    }
+
+    fn plaintext(inner: C, read_buf: Vec<u8>) -> Self {
+        Self {
+            inner,
+            mode: CryptoMode::Plaintext,
+            encryptor: None,
+            decryptor: None,
+            read_buf,
+            write_buf: Vec::new(),
+        }
+    }
+
+    /// Returns the negotiated crypto method.
+    pub fn mode(&self) -> CryptoMode {
+        self.mode
+    }
+}
+
+impl<C: Write + Unpin> EncryptedStream<C> {
+    fn poll_write_buf(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while !self.write_buf.is_empty() {
+            let n = ready!(Pin::new(&mut self.inner).poll_write(cx, &self.write_buf))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.write_buf.drain(..n);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<C: Read + Unpin> Read for EncryptedStream<C> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if !this.read_buf.is_empty() {
+            let n = buf.len().min(this.read_buf.len());
+            buf[..n].copy_from_slice(&this.read_buf[..n]);
+            this.read_buf.drain(..n);
+            return Poll::Ready(Ok(n));
+        }
+        let n = ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+        if let Some(decryptor) = &mut this.decryptor {
+            decryptor.apply(&mut buf[..n]);
+        }
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl<C: Write + Unpin> Write for EncryptedStream<C> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if this.encryptor.is_none() {
+            return Pin::new(&mut this.inner).poll_write(cx, buf);
+        }
+        // the keystream advances on encryption, so encrypted bytes are kept until written
+        ready!(this.poll_write_buf(cx))?;
+        let start = this.write_buf.len();
+        this.write_buf.extend_from_slice(buf);
+        if let Some(encryptor) = &mut this.encryptor {
+            encryptor.apply(&mut this.write_buf[start..]);
+        }
+        if let Poll::Ready(Err(err)) = this.poll_write_buf(cx) {
+            return Poll::Ready(Err(err));
+        }
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_buf(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_buf(cx))?;
+        Pin::new(&mut this.inner).poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_std::net::{TcpListener, TcpStream};
+    use async_std::task::{block_on, spawn};
+    use futures::io::Cursor;
+
+    #[test]
+    fn test_rc4() {
+        let mut buf = *b"Plaintext";
+        Rc4::new(b"Key").apply(&mut buf);
+        assert_eq!(buf, [0xbb, 0xf3, 0x16, 0xe8, 0xd9, 0x40, 0xaf, 0x0a, 0xd3]);
+    }
+
+    #[test]
+    fn test_mse_handshake() {
+        block_on(async {
+            let info_hash = HashPiece::rand_new();
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let receiver_info_hash = info_hash.clone();
+            let handle = spawn(async move {
+                let (conn, _) = listener.accept().await.unwrap();
+                let mut stream = accept(conn, &receiver_info_hash, EncryptionPolicy::default())
+                    .await
+                    .unwrap();
+                assert_eq!(stream.mode(), CryptoMode::Rc4);
+                let mut buf = vec![0u8; 64 * 1024];
+                stream.read_exact(&mut buf).await.unwrap();
+                assert!(buf.iter().enumerate().all(|(i, byte)| *byte == i as u8));
+                stream.write_all(b"world").await.unwrap();
+                stream.flush().await.unwrap();
+            });
+
+            let conn = TcpStream::connect(addr).await.unwrap();
+            let mut stream = initiate(conn, &info_hash, EncryptionPolicy::Require)
+                .await
+                .unwrap();
+            assert_eq!(stream.mode(), CryptoMode::Rc4);
+            let buf: Vec<u8> = (0..64 * 1024).map(|i| i as u8).collect();
+            stream.write_all(&buf).await.unwrap();
+            stream.flush().await.unwrap();
+            let mut buf = [0u8; 5];
+            stream.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"world");
+            handle.await;
+
+            // the receiver doesn't serve the torrent
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let handle = spawn(async move {
+                let (conn, _) = listener.accept().await.unwrap();
+                accept(conn, &HashPiece::rand_new(), EncryptionPolicy::default())
+                    .await
+                    .map(|_| ())
+            });
+            let conn = TcpStream::connect(addr).await.unwrap();
+            let _ = initiate(conn, &info_hash, EncryptionPolicy::default()).await;
+            assert!(matches!(handle.await, Err(Error::InvaildInfoHash)));
+        });
+    }
+
+    #[test]
+    fn test_mse_initiator_plaintext_fallback() {
+        block_on(async {
+            let mut handshake = PLAINTEXT_PREFIX.to_vec();
+            handshake.extend_from_slice(&[0; 48]);
+            // a peer that doesn't know MSE drops a connection not starting with a handshake
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let remote_handshake = handshake.clone();
+            let handle = spawn(async move {
+                let mut accepted = 0;
+                loop {
+                    let (mut conn, _) = listener.accept().await.unwrap();
+                    accepted += 1;
+                    let mut buf = vec![0u8; remote_handshake.len()];
+                    conn.read_exact(&mut buf).await.unwrap();
+                    if buf == remote_handshake {
+                        conn.write_all(b"hello").await.unwrap();
+                        return accepted;
+                    }
+                }
+            });
+
+            let open = || TcpStream::connect(addr);
+            let mut stream = connect(open, &HashPiece::rand_new(), EncryptionPolicy::Prefer)
+                .await
+                .unwrap();
+            assert_eq!(stream.mode(), CryptoMode::Plaintext);
+            stream.write_all(&handshake).await.unwrap();
+            let mut buf = [0u8; 5];
+            stream.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"hello");
+            assert_eq!(handle.await, 2);
+
+            // without fallback the failure is returned
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            spawn(async move {
+                let (mut conn, _) = listener.accept().await.unwrap();
+                let mut buf = [0u8; 68];
+                conn.read_exact(&mut buf).await.unwrap();
+            });
+            let open = || TcpStream::connect(addr);
+            assert!(
+                connect(open, &HashPiece::rand_new(), EncryptionPolicy::Require)
+                    .await
+                    .is_err()
+            );
+        });
+    }
+
+    #[test]
+    fn test_mse_plaintext_fallback() {
+        block_on(async {
+            let mut handshake = PLAINTEXT_PREFIX.to_vec();
+            handshake.extend_from_slice(&[0; 48]);
+            let conn = Cursor::new(handshake.clone());
+            let mut stream = accept(conn, &HashPiece::rand_new(), EncryptionPolicy::Prefer)
+                .await
+                .unwrap();
+            assert_eq!(stream.mode(), CryptoMode::Plaintext);
+            let mut buf = Vec::new();
+            stream.read_to_end(&mut buf).await.unwrap();
+            assert_eq!(buf, handshake);
+
+            assert!(matches!(
+                accept(
+                    Cursor::new(handshake),
+                    &HashPiece::rand_new(),
+                    EncryptionPolicy::Require
+                )
+                .await,
+                Err(Error::EncryptionHandshake(_))
+            ));
+        });
+    }
+}
@@ -0,0 +1,174 @@
+//! This module implements the peer exchange extension defined in https://www.bittorrent.org/beps/bep_0011.html
+
+use super::error::{Error, Result};
+use crate::metainfo::{
+    from_compact_list, to_compact_list, PeerAddress, ADDRESS_V4_LEN, ADDRESS_V6_LEN,
+};
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use serde_bencode::{de::from_bytes, ser::to_bytes};
+use std::time::Duration;
+
+/// Name of the extension in the `m` dictionary of the extended handshake.
+pub(crate) const UT_PEX: &str = "ut_pex";
+/// Extended message id local expects for ut_pex messages.
+pub(crate) const LOCAL_UT_PEX_ID: u8 = 2;
+/// A peer must not send more than one PEX message per minute.
+pub(crate) const PEX_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Bencoded ut_pex payload, the addresses are strings of compact addresses.
+#[derive(Debug, Serialize, Deserialize, Default)]
+struct RawPexMessage {
+    #[serde(with = "serde_bytes", skip_serializing_if = "Vec::is_empty", default)]
+    added: Vec<u8>,
+    #[serde(rename = "added.f")]
+    #[serde(with = "serde_bytes", skip_serializing_if = "Vec::is_empty", default)]
+    added_flags: Vec<u8>,
+    #[serde(with = "serde_bytes", skip_serializing_if = "Vec::is_empty", default)]
+    added6: Vec<u8>,
+    #[serde(rename = "added6.f")]
+    #[serde(with = "serde_bytes", skip_serializing_if = "Vec::is_empty", default)]
+    added6_flags: Vec<u8>,
+    #[serde(with = "serde_bytes", skip_serializing_if = "Vec::is_empty", default)]
+    dropped: Vec<u8>,
+    #[serde(with = "serde_bytes", skip_serializing_if = "Vec::is_empty", default)]
+    dropped6: Vec<u8>,
+}
+
+/// PexPeer is a peer added by a PEX message.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct PexPeer {
+    pub address: PeerAddress,
+    /// bit flags such as 0x01 prefers encryption, 0x02 is seed
+    pub flags: u8,
+}
+
+/// PexMessage is the payload of an extended message of ut_pex.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct PexMessage {
+    /// Peers connected since the last PEX message
+    pub added: Vec<PexPeer>,
+    /// Peers disconnected since the last PEX message
+    pub dropped: Vec<PeerAddress>,
+}
+
+impl PexMessage {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.dropped.is_empty()
+    }
+
+    pub fn from_payload(payload: &[u8]) -> Result<Self> {
+        let raw: RawPexMessage = from_bytes(payload)?;
+        let mut added = Vec::new();
+        for (buf, flags, entry_len) in [
+            (&raw.added, &raw.added_flags, ADDRESS_V4_LEN),
+            (&raw.added6, &raw.added6_flags, ADDRESS_V6_LEN),
+        ] {
+            let addresses = from_compact_list(buf, entry_len).ok_or(Error::InvalidPexMessage)?;
+            // flags are optional, missing ones are treated as zero
+            added.extend(
+                addresses
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, address)| PexPeer {
+                        address,
+                        flags: flags.get(i).copied().unwrap_or_default(),
+                    }),
+            );
+        }
+        let mut dropped =
+            from_compact_list(&raw.dropped, ADDRESS_V4_LEN).ok_or(Error::InvalidPexMessage)?;
+        dropped.extend(
+            from_compact_list(&raw.dropped6, ADDRESS_V6_LEN).ok_or(Error::InvalidPexMessage)?,
+        );
+        Ok(Self { added, dropped })
+    }
+
+    pub fn to_payload(&self) -> Result<Bytes> {
+        let (added, added6): (Vec<&PexPeer>, Vec<&PexPeer>) =
+            self.added.iter().partition(|peer| peer.address.0.is_ipv4());
+        let (dropped, dropped6): (Vec<&PeerAddress>, Vec<&PeerAddress>) =
+            self.dropped.iter().partition(|address| address.0.is_ipv4());
+        let raw = RawPexMessage {
+            added: to_compact_list(added.iter().map(|peer| &peer.address)),
+            added_flags: added.iter().map(|peer| peer.flags).collect(),
+            added6: to_compact_list(added6.iter().map(|peer| &peer.address)),
+            added6_flags: added6.iter().map(|peer| peer.flags).collect(),
+            dropped: to_compact_list(dropped),
+            dropped6: to_compact_list(dropped6),
+        };
+        Ok(to_bytes(&raw)?.into())
+    }
+
+    /// Merge a later message into this one, a peer added then dropped cancels out.
+    pub(crate) fn merge(&mut self, other: PexMessage) {
+        for address in other.dropped {
+            let len = self.added.len();
+            self.added.retain(|peer| peer.address != address);
+            if len == self.added.len() && !self.dropped.contains(&address) {
+                self.dropped.push(address);
+            }
+        }
+        for peer in other.added {
+            self.dropped.retain(|address| *address != peer.address);
+            if !self.added.iter().any(|added| added.address == peer.address) {
+                self.added.push(peer);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pex_message() {
+        let message = PexMessage {
+            added: vec![
+                PexPeer {
+                    address: PeerAddress("1.2.3.4:1234".parse().unwrap()),
+                    flags: 0x02,
+                },
+                PexPeer {
+                    address: PeerAddress("[2001:db8::1]:6881".parse().unwrap()),
+                    flags: 0x01,
+                },
+            ],
+            dropped: vec![PeerAddress("5.6.7.8:6881".parse().unwrap())],
+        };
+        let payload = message.to_payload().unwrap();
+        assert_eq!(
+            &payload[..],
+            &b"d5:added6:\x01\x02\x03\x04\x04\xd27:added.f1:\x02\
+                6:added618:\x20\x01\x0d\xb8\0\0\0\0\0\0\0\0\0\0\0\x01\x1a\xe1\
+                8:added6.f1:\x01\
+                7:dropped6:\x05\x06\x07\x08\x1a\xe1e"[..]
+        );
+        assert_eq!(PexMessage::from_payload(&payload).unwrap(), message);
+        assert!(PexMessage::from_payload(b"d5:added5:\x01\x02\x03\x04\x04e").is_err());
+    }
+
+    #[test]
+    fn test_pex_merge() {
+        let a = PeerAddress("1.2.3.4:1234".parse().unwrap());
+        let b = PeerAddress("5.6.7.8:6881".parse().unwrap());
+        let mut message = PexMessage {
+            added: vec![PexPeer {
+                address: a.clone(),
+                flags: 0,
+            }],
+            dropped: vec![],
+        };
+        message.merge(PexMessage {
+            added: vec![PexPeer {
+                address: b.clone(),
+                flags: 0,
+            }],
+            dropped: vec![a],
+        });
+        assert_eq!(message.added.len(), 1);
+        assert_eq!(message.added[0].address, b);
+        assert!(message.dropped.is_empty());
+    }
+}
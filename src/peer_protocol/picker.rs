@@ -0,0 +1,221 @@
+//! Rarest-first piece selection, pieces few peers have are downloaded first
+//! so they spread through the swarm.
+
+use super::bitfield::BitField;
+use super::error::{Error, Result};
+use rand::seq::IteratorRandom;
+
+/// Size of the blocks a piece is requested in.
+pub const BLOCK_LEN: usize = 16 * 1024;
+/// Pieces are picked at random until this many are downloaded,
+/// a complete piece is needed quickly to have something to upload.
+const RANDOM_FIRST_PIECES: usize = 4;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum PieceState {
+    Missing,
+    /// some blocks are requested, `next_begin` is the offset of the next one
+    Downloading {
+        next_begin: usize,
+    },
+    /// every block is requested
+    Requested,
+    Done,
+}
+
+/// PiecePicker decides which block to request next from a peer.
+#[derive(Debug)]
+pub struct PiecePicker {
+    piece_length: usize,
+    total_length: u64,
+    /// number of connected peers having each piece
+    availability: Vec<usize>,
+    states: Vec<PieceState>,
+    done_count: usize,
+}
+
+impl PiecePicker {
+    pub fn new(piece_length: usize, total_length: u64) -> Result<Self> {
+        if piece_length == 0 {
+            return Err(Error::InvalidPieceLength(piece_length));
+        }
+        let piece_count = ((total_length + piece_length as u64 - 1) / piece_length as u64) as usize;
+        Ok(PiecePicker {
+            piece_length,
+            total_length,
+            availability: vec![0; piece_count],
+            states: vec![PieceState::Missing; piece_count],
+            done_count: 0,
+        })
+    }
+
+    /// A peer announced the piece with a have message.
+    pub fn peer_has(&mut self, piece_index: usize) {
+        if let Some(count) = self.availability.get_mut(piece_index) {
+            *count += 1;
+        }
+    }
+
+    /// A peer connected with the pieces of `bitfield`.
+    pub fn add_peer(&mut self, bitfield: &BitField) {
+        for (piece_index, count) in self.availability.iter_mut().enumerate() {
+            if bitfield.has(piece_index) {
+                *count += 1;
+            }
+        }
+    }
+
+    /// A peer having the pieces of `bitfield` disconnected.
+    pub fn remove_peer(&mut self, bitfield: &BitField) {
+        for (piece_index, count) in self.availability.iter_mut().enumerate() {
+            if bitfield.has(piece_index) {
+                *count = count.saturating_sub(1);
+            }
+        }
+    }
+
+    /// The piece is downloaded and verified.
+    pub fn piece_done(&mut self, piece_index: usize) {
+        if let Some(state) = self.states.get_mut(piece_index) {
+            if *state != PieceState::Done {
+                *state = PieceState::Done;
+                self.done_count += 1;
+            }
+        }
+    }
+
+    /// The piece failed verification or its requests were lost, it is picked again.
+    pub fn piece_failed(&mut self, piece_index: usize) {
+        if let Some(state) = self.states.get_mut(piece_index) {
+            if *state == PieceState::Done {
+                self.done_count -= 1;
+            }
+            *state = PieceState::Missing;
+        }
+    }
+
+    /// Length of the piece, the last one may be shorter, `None` if the piece is out of range.
+    pub fn piece_len(&self, piece_index: usize) -> Option<usize> {
+        if piece_index >= self.states.len() {
+            return None;
+        }
+        let begin = piece_index as u64 * self.piece_length as u64;
+        Some((self.total_length - begin).min(self.piece_length as u64) as usize)
+    }
+
+    /// Pick the next block to request from a peer having `peer_pieces`,
+    /// returns the piece index, the offset and the length of the block.
+    pub fn next_block(&mut self, peer_pieces: &BitField) -> Option<(usize, usize, usize)> {
+        let piece_index = self.pick_piece(peer_pieces)?;
+        let begin = match self.states[piece_index] {
+            PieceState::Downloading { next_begin } => next_begin,
+            _ => 0,
+        };
+        let piece_len = self.piece_len(piece_index)?;
+        let len = BLOCK_LEN.min(piece_len - begin);
+        self.states[piece_index] = if begin + len == piece_len {
+            PieceState::Requested
+        } else {
+            PieceState::Downloading {
+                next_begin: begin + len,
+            }
+        };
+        Some((piece_index, begin, len))
+    }
+
+    /// Pieces already being downloaded are finished first, then the rarest missing piece is picked.
+    fn pick_piece(&self, peer_pieces: &BitField) -> Option<usize> {
+        let rarest = |state: fn(&PieceState) -> bool| {
+            self.states
+                .iter()
+                .enumerate()
+                .filter(|(piece_index, piece_state)| {
+                    state(piece_state) && peer_pieces.has(*piece_index)
+                })
+                .min_by_key(|(piece_index, _)| self.availability[*piece_index])
+                .map(|(piece_index, _)| piece_index)
+        };
+        if let Some(piece_index) = rarest(|state| matches!(state, PieceState::Downloading { .. })) {
+            return Some(piece_index);
+        }
+        if self.done_count < RANDOM_FIRST_PIECES {
+            return self
+                .states
+                .iter()
+                .enumerate()
+                .filter(|(piece_index, state)| {
+                    **state == PieceState::Missing && peer_pieces.has(*piece_index)
+                })
+                .map(|(piece_index, _)| piece_index)
+                .choose(&mut rand::thread_rng());
+        }
+        rarest(|state| *state == PieceState::Missing)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_picker_rarest_first() {
+        let piece_length = 2 * BLOCK_LEN;
+        let mut picker = PiecePicker::new(piece_length, 7 * piece_length as u64 + 100).unwrap();
+        assert_eq!(picker.piece_len(0), Some(piece_length));
+        assert_eq!(picker.piece_len(7), Some(100));
+
+        // at startup a random piece the peer has is picked
+        let mut peer = BitField::new(8);
+        peer.set(3);
+        peer.set(5);
+        let (piece_index, begin, len) = picker.next_block(&peer).unwrap();
+        assert!(piece_index == 3 || piece_index == 5);
+        assert_eq!((begin, len), (0, BLOCK_LEN));
+        picker.piece_failed(piece_index);
+
+        for piece_index in 0..4 {
+            picker.piece_done(piece_index);
+        }
+        let full = BitField::full(8);
+        for (piece_index, count) in [(4, 3), (5, 2), (6, 1), (7, 2)] {
+            for _ in 0..count {
+                picker.peer_has(piece_index);
+            }
+        }
+        picker.add_peer(&full);
+        assert_eq!(picker.next_block(&full), Some((6, 0, BLOCK_LEN)));
+        // the started piece is finished before another one is picked
+        assert_eq!(picker.next_block(&full), Some((6, BLOCK_LEN, BLOCK_LEN)));
+        // ties are broken by the lowest index
+        assert_eq!(picker.next_block(&full), Some((5, 0, BLOCK_LEN)));
+        assert_eq!(picker.next_block(&full), Some((5, BLOCK_LEN, BLOCK_LEN)));
+        assert_eq!(picker.next_block(&full), Some((7, 0, 100)));
+        assert_eq!(picker.next_block(&full), Some((4, 0, BLOCK_LEN)));
+
+        let mut peer = BitField::new(8);
+        peer.set(6);
+        assert_eq!(picker.next_block(&peer), None);
+        picker.piece_failed(6);
+        assert_eq!(picker.next_block(&peer), Some((6, 0, BLOCK_LEN)));
+
+        picker.remove_peer(&full);
+        assert_eq!(picker.availability[4..], [3, 2, 1, 2]);
+    }
+
+    #[test]
+    fn test_picker_invalid_input() {
+        assert!(matches!(
+            PiecePicker::new(0, 100),
+            Err(Error::InvalidPieceLength(0))
+        ));
+        let mut picker = PiecePicker::new(BLOCK_LEN, 2 * BLOCK_LEN as u64).unwrap();
+        assert_eq!(picker.piece_len(2), None);
+        assert_eq!(picker.piece_len(usize::MAX), None);
+        // pieces out of range are ignored
+        picker.peer_has(2);
+        picker.piece_done(2);
+        picker.piece_failed(usize::MAX);
+        assert_eq!(picker.done_count, 0);
+        assert_eq!(picker.states, vec![PieceState::Missing; 2]);
+    }
+}
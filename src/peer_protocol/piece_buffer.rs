@@ -0,0 +1,137 @@
+//! Assembles the blocks of a piece and verifies it against the SHA1 hash of the info.
+
+use super::error::{Error, Result};
+use super::picker::BLOCK_LEN;
+use crate::metainfo::{HashPiece, Info};
+
+/// PieceBuffer accumulates the blocks of a piece received in any order.
+#[derive(Debug)]
+pub struct PieceBuffer {
+    piece_index: usize,
+    data: Vec<u8>,
+    /// which blocks have been received
+    received: Vec<bool>,
+    /// number of blocks not received yet
+    missing: usize,
+}
+
+impl PieceBuffer {
+    /// Create the buffer of a piece of `info`, the last piece may be shorter.
+    pub fn new(info: &Info, piece_index: usize) -> Result<Self> {
+        let begin = piece_index as u64 * info.piece_length;
+        let total_length = info.total_length();
+        if piece_index >= info.pieces.0.len() || begin >= total_length {
+            return Err(Error::InvalidPiece(piece_index));
+        }
+        let piece_len = (total_length - begin).min(info.piece_length) as usize;
+        let block_count = (piece_len + BLOCK_LEN - 1) / BLOCK_LEN;
+        Ok(PieceBuffer {
+            piece_index,
+            data: vec![0; piece_len],
+            received: vec![false; block_count],
+            missing: block_count,
+        })
+    }
+
+    pub fn piece_index(&self) -> usize {
+        self.piece_index
+    }
+
+    /// Add a block received with a piece message, blocks received twice are ignored.
+    pub fn add_block(&mut self, block_begin: usize, block_data: &[u8]) -> Result<()> {
+        let block = block_begin / BLOCK_LEN;
+        let expected_len = BLOCK_LEN.min(self.data.len().saturating_sub(block_begin));
+        if block_begin % BLOCK_LEN != 0
+            || block >= self.received.len()
+            || block_data.len() != expected_len
+        {
+            return Err(Error::InvalidBlock {
+                piece_index: self.piece_index,
+                block_begin,
+                block_length: block_data.len(),
+            });
+        }
+        if !self.received[block] {
+            self.data[block_begin..block_begin + block_data.len()].copy_from_slice(block_data);
+            self.received[block] = true;
+            self.missing -= 1;
+        }
+        Ok(())
+    }
+
+    /// Have all blocks been received?
+    pub fn is_complete(&self) -> bool {
+        self.missing == 0
+    }
+
+    /// Check the complete piece against its hash in `info` and return its data,
+    /// on a mismatch the piece has to be downloaded again.
+    pub fn verify(self, info: &Info) -> Result<Vec<u8>> {
+        if !self.is_complete() {
+            return Err(Error::PieceIncomplete(self.piece_index));
+        }
+        if HashPiece::from(self.data.as_slice()) != info.pieces.0[self.piece_index] {
+            return Err(Error::PieceHashMismatch(self.piece_index));
+        }
+        Ok(self.data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metainfo::HashPieces;
+    use rand::seq::SliceRandom;
+
+    fn info(data: &[u8], piece_length: usize) -> Info {
+        Info {
+            name: "a.txt".to_string(),
+            piece_length: piece_length as u64,
+            pieces: HashPieces(data.chunks(piece_length).map(HashPiece::from).collect()),
+            length: Some(data.len() as u64),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_piece_buffer() {
+        let piece_length = 4 * BLOCK_LEN;
+        let data: Vec<u8> = (0..piece_length + BLOCK_LEN + 10)
+            .map(|i| i as u8)
+            .collect();
+        let info = info(&data, piece_length);
+
+        let mut buffer = PieceBuffer::new(&info, 0).unwrap();
+        let mut blocks: Vec<usize> = (0..4).map(|block| block * BLOCK_LEN).collect();
+        blocks.shuffle(&mut rand::thread_rng());
+        for begin in blocks {
+            assert!(!buffer.is_complete());
+            buffer
+                .add_block(begin, &data[begin..begin + BLOCK_LEN])
+                .unwrap();
+        }
+        buffer.add_block(0, &data[..BLOCK_LEN]).unwrap();
+        assert_eq!(buffer.verify(&info).unwrap(), data[..piece_length]);
+
+        // the last piece is a full block and a short one
+        let mut buffer = PieceBuffer::new(&info, 1).unwrap();
+        let last = &data[piece_length..];
+        assert!(buffer.add_block(BLOCK_LEN, &last[BLOCK_LEN - 1..]).is_err());
+        assert!(buffer.add_block(1, &last[1..BLOCK_LEN + 1]).is_err());
+        buffer.add_block(BLOCK_LEN, &last[BLOCK_LEN..]).unwrap();
+        let mut corrupted = last[..BLOCK_LEN].to_vec();
+        corrupted[7] ^= 0xff;
+        buffer.add_block(0, &corrupted).unwrap();
+        assert!(matches!(
+            buffer.verify(&info),
+            Err(Error::PieceHashMismatch(1))
+        ));
+
+        let buffer = PieceBuffer::new(&info, 1).unwrap();
+        assert!(matches!(
+            buffer.verify(&info),
+            Err(Error::PieceIncomplete(1))
+        ));
+        assert!(PieceBuffer::new(&info, 2).is_err());
+    }
+}
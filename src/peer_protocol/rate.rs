@@ -0,0 +1,80 @@
+use std::time::{Duration, Instant};
+
+/// Traffic is folded into the average at most once per interval.
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+/// Time constant of the moving average in seconds, older samples fade out exponentially.
+const TIME_CONSTANT: f64 = 5.0;
+
+/// Rate measures the throughput of one direction of a connection
+/// as an exponential moving average of bytes per second.
+#[derive(Debug, Clone)]
+pub struct Rate {
+    /// total bytes transferred
+    total: u64,
+    /// bytes transferred since `sampled_at`, not folded into the average yet
+    pending: u64,
+    sampled_at: Instant,
+    /// bytes per second as of `sampled_at`
+    average: f64,
+}
+
+impl Rate {
+    pub fn new(now: Instant) -> Self {
+        Rate {
+            total: 0,
+            pending: 0,
+            sampled_at: now,
+            average: 0.0,
+        }
+    }
+
+    /// Record `bytes` transferred at `now`.
+    pub fn record(&mut self, bytes: usize, now: Instant) {
+        self.total += bytes as u64;
+        self.pending += bytes as u64;
+        if now.saturating_duration_since(self.sampled_at) >= SAMPLE_INTERVAL {
+            self.average = self.rate(now);
+            self.pending = 0;
+            self.sampled_at = now;
+        }
+    }
+
+    /// Bytes per second at `now`, idle time since the last sample decays the rate.
+    pub fn rate(&self, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(self.sampled_at);
+        if elapsed < SAMPLE_INTERVAL {
+            return self.average;
+        }
+        let elapsed = elapsed.as_secs_f64();
+        let sample = self.pending as f64 / elapsed;
+        let weight = 1.0 - (-elapsed / TIME_CONSTANT).exp();
+        self.average + weight * (sample - self.average)
+    }
+
+    /// Total bytes transferred.
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate() {
+        let start = Instant::now();
+        let mut rate = Rate::new(start);
+        // 16 KiB blocks, 4 of them per second
+        for i in 1..=120 {
+            rate.record(16 * 1024, start + Duration::from_millis(250 * i));
+        }
+        let now = start + Duration::from_secs(30);
+        assert_eq!(rate.total(), 120 * 16 * 1024);
+        assert!((rate.rate(now) - 65536.0).abs() < 65536.0 * 0.05);
+
+        // the rate fades out once the traffic stops
+        assert!(rate.rate(now + Duration::from_secs(5)) < 65536.0 * 0.5);
+        assert!(rate.rate(now + Duration::from_secs(60)) < 1.0);
+    }
+}
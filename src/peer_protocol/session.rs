@@ -1,17 +1,143 @@
+use super::bitfield::{BitField, MAX_PIECE_COUNT};
 use super::error::{Error, Result};
-use super::message::{HandshakeMessage, HandshakeMessageCodec, Message, MessageCodec};
-use crate::metainfo;
-use async_std::channel::Receiver;
-use async_std::io::{Read, Write};
-use asynchronous_codec::Framed;
-use futures::{SinkExt, StreamExt};
+use super::extension::{ExtendedHandshake, EXTENDED_HANDSHAKE_ID};
+use super::message::{
+    HandshakeMessage, HandshakeMessageCodec, Message, MessageCodec, HANDSHAKE_LEN,
+};
+use super::metadata::{MetadataAssembler, MetadataMessage, LOCAL_UT_METADATA_ID, UT_METADATA};
+use super::pex::{PexMessage, LOCAL_UT_PEX_ID, PEX_INTERVAL, UT_PEX};
+use super::rate::Rate;
+use crate::metainfo::{self, Info, PeerAddress};
+use async_std::channel::{Receiver, Sender};
+use async_std::io::{prelude::*, Read, Write};
+use async_std::task;
+use asynchronous_codec::{BytesMut, Decoder, Encoder, Framed};
+use bytes::Bytes;
+use futures::future::{self, Either};
+use futures::{pin_mut, SinkExt, StreamExt};
+use std::collections::{HashMap, VecDeque};
+use std::net::{IpAddr, SocketAddr};
+use std::ops::DerefMut;
+use std::time::{Duration, Instant};
 
-pub enum Command {}
+/// Command is sent to a running session to drive the remote peer.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Command {
+    /// Request a block of a piece, queued until the remote peer unchokes us
+    RequestPiece {
+        piece_index: usize,
+        block_begin: usize,
+        block_length: usize,
+    },
+    /// Withdraw a block request, a request already sent is cancelled
+    CancelPiece {
+        piece_index: usize,
+        block_begin: usize,
+        block_length: usize,
+    },
+    /// Send a block of a piece the remote peer requested
+    SendPiece {
+        piece_index: usize,
+        block_begin: usize,
+        block_data: Bytes,
+    },
+    /// Stop allowing the remote peer to download from local
+    Choke,
+    /// Allow the remote peer to download from local
+    Unchoke,
+    /// Tell the remote peer whether local is interested in its pieces
+    SetInterested(bool),
+    /// Announce that local has completed the piece
+    SendHave(usize),
+    /// Tell the remote peer about connected and disconnected peers with ut_pex
+    SendPex(PexMessage),
+    /// Advise the remote peer to download the piece, needs the fast extension
+    SuggestPiece(usize),
+    /// Allow the remote peer to request the piece while choked, needs the fast extension
+    AllowFast(usize),
+    /// Stop the session and close the connection
+    Shutdown,
+}
+
+/// SessionEvent is sent by a running session to report what the remote peer did.
+#[derive(Debug, PartialEq, Eq)]
+pub enum SessionEvent {
+    /// A block requested from the remote peer arrived
+    BlockReceived {
+        piece_index: usize,
+        block_begin: usize,
+        block_data: Bytes,
+    },
+    /// The remote peer unchoked local, requests are sent
+    Unchoked,
+    /// The remote peer choked local, requests are held back until it unchokes
+    Choked,
+    /// The remote peer, unchoked by local, requests a block to be sent with `Command::SendPiece`
+    BlockRequested {
+        piece_index: usize,
+        block_begin: usize,
+        block_length: usize,
+    },
+    /// The remote peer withdraws a block request
+    BlockCancelled {
+        piece_index: usize,
+        block_begin: usize,
+        block_length: usize,
+    },
+    /// The remote peer became interested, or not interested anymore, in pieces of local
+    Interested(bool),
+}
+
+/// DhtHook hands the DHT port advertised by the remote peer to the local DHT node.
+struct DhtHook {
+    /// port the local DHT node listens on
+    port: u16,
+    /// ip of the remote peer
+    peer_ip: IpAddr,
+    /// communication channel to the local DHT node
+    dht_tx: Sender<SocketAddr>,
+}
+
+/// Wakeup tells what the message loop is woken up by.
+enum Wakeup {
+    /// `None` once all senders are dropped
+    Command(Option<Command>),
+    /// `None` once the connection is closed
+    Message(Option<Result<Message>>),
+    /// the held back ut_pex message can be sent
+    PexDue,
+}
+
+/// A block request waiting to be sent.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub(crate) struct BlockRequest {
+    pub(crate) piece_index: usize,
+    pub(crate) block_begin: usize,
+    pub(crate) block_length: usize,
+}
+
+impl BlockRequest {
+    pub(crate) fn to_request(self) -> Command {
+        Command::RequestPiece {
+            piece_index: self.piece_index,
+            block_begin: self.block_begin,
+            block_length: self.block_length,
+        }
+    }
+
+    pub(crate) fn to_cancel(self) -> Command {
+        Command::CancelPiece {
+            piece_index: self.piece_index,
+            block_begin: self.block_begin,
+            block_length: self.block_length,
+        }
+    }
+}
 
 /// Session represents a connection context to a peer.
 pub struct Session<C> {
     /// inner connection to peer
-    conn: C,
+    conn: Framed<C, MessageCodec>,
     /// communication channel to send command to seesion
     cmd_rx: Receiver<Command>,
     /// hash of the torrent info
@@ -32,6 +158,40 @@ pub struct Session<C> {
     handshake_done: bool,
     /// maximum number of pieces that can be requested at once
     max_request_queue_len: usize,
+    /// requests waiting for the remote peer to unchoke us
+    pending_requests: VecDeque<BlockRequest>,
+    /// requests sent to the remote peer and not answered yet
+    in_flight_requests: VecDeque<BlockRequest>,
+    /// remote peer supports the extension protocol
+    peer_extension_protocol: bool,
+    /// remote peer supports the fast extension
+    peer_fast_extension: bool,
+    /// number of pieces of the torrent, zero if unknown yet
+    piece_count: usize,
+    /// pieces the remote peer has
+    peer_pieces: BitField,
+    /// remote peer sent `HaveAll`, `peer_pieces` is full once the piece count is known
+    peer_have_all: bool,
+    /// extension names mapped to the extended message ids the remote peer expects
+    peer_extensions: HashMap<String, u8>,
+    /// has the extended handshake of the remote peer been received?
+    peer_extended_handshake_done: bool,
+    /// size of the info dictionary announced by the remote peer
+    peer_metadata_size: Option<usize>,
+    /// communication channel to report what the remote peer did
+    event_tx: Option<Sender<SessionEvent>>,
+    /// communication channel to hand out peers learned from ut_pex
+    pex_tx: Option<Sender<Vec<PeerAddress>>>,
+    /// changes of the swarm waiting to be sent with ut_pex
+    pending_pex: PexMessage,
+    /// when the last ut_pex message was sent
+    pex_sent_at: Option<Instant>,
+    /// set if local runs a DHT node
+    dht: Option<DhtHook>,
+    /// piece data received from the remote peer
+    download: Rate,
+    /// piece data sent to the remote peer
+    upload: Rate,
 }
 
 impl<C: Read + Write + Unpin> Session<C> {
@@ -43,7 +203,7 @@ impl<C: Read + Write + Unpin> Session<C> {
         id: metainfo::HashPiece,
     ) -> Self {
         Session {
-            conn,
+            conn: Framed::new(conn, MessageCodec),
             cmd_rx,
             info_hash,
             id,
@@ -54,41 +214,1460 @@ impl<C: Read + Write + Unpin> Session<C> {
             peer_interested: false,
             handshake_done: false,
             max_request_queue_len: 16,
+            pending_requests: VecDeque::new(),
+            in_flight_requests: VecDeque::new(),
+            peer_extension_protocol: false,
+            peer_fast_extension: false,
+            piece_count: 0,
+            peer_pieces: BitField::default(),
+            peer_have_all: false,
+            peer_extensions: HashMap::new(),
+            peer_extended_handshake_done: false,
+            peer_metadata_size: None,
+            event_tx: None,
+            pex_tx: None,
+            pending_pex: PexMessage::default(),
+            pex_sent_at: None,
+            dht: None,
+            download: Rate::new(Instant::now()),
+            upload: Rate::new(Instant::now()),
+        }
+    }
+
+    /// Create a new session whose local peer id is generated from `client_prefix`,
+    /// usually [`CLIENT_PREFIX`](metainfo::CLIENT_PREFIX).
+    pub fn with_client_prefix(
+        conn: C,
+        cmd_rx: Receiver<Command>,
+        info_hash: metainfo::HashPiece,
+        client_prefix: &[u8; 8],
+    ) -> Self {
+        let id = metainfo::HashPiece::peer_id(client_prefix);
+        Self::new(conn, cmd_rx, info_hash, id)
+    }
+
+    /// Advertise the local DHT node listening on `port`.
+    /// The DHT address of the remote peer at `peer_ip` is sent to `dht_tx` once it sends a port message,
+    /// so that the DHT node can ping it.
+    pub fn enable_dht(&mut self, port: u16, peer_ip: IpAddr, dht_tx: Sender<SocketAddr>) {
+        self.dht = Some(DhtHook {
+            port,
+            peer_ip,
+            dht_tx,
+        });
+    }
+
+    /// Set the channel receiving the events of the session, such as the blocks downloaded.
+    pub fn set_event_sender(&mut self, event_tx: Sender<SessionEvent>) {
+        self.event_tx = Some(event_tx);
+    }
+
+    /// Set the channel receiving the peers the remote peer tells about with ut_pex.
+    pub fn set_pex_sender(&mut self, pex_tx: Sender<Vec<PeerAddress>>) {
+        self.pex_tx = Some(pex_tx);
+    }
+
+    /// Set the number of pieces of the torrent, needed to interpret `HaveAll`.
+    /// The pieces the remote peer announced before are kept, it fails if the remote peer
+    /// announced pieces beyond `piece_count`.
+    pub fn set_piece_count(&mut self, piece_count: usize) -> Result<()> {
+        if self.peer_have_all {
+            self.peer_pieces = BitField::full(piece_count);
+        } else if self.peer_pieces.len() != piece_count {
+            // a raw bitfield is padded to whole bytes, pieces announced by `Have` may end earlier
+            let mut bytes = self.peer_pieces.as_bytes().to_vec();
+            let len = bytes.len().max(BitField::byte_len(piece_count));
+            bytes.resize(len, 0);
+            self.peer_pieces = BitField::from_bytes(&bytes, piece_count)?;
         }
+        self.piece_count = piece_count;
+        Ok(())
+    }
+
+    /// Number of requests sent to the remote peer and not answered yet.
+    pub fn in_flight_count(&self) -> usize {
+        self.in_flight_requests.len()
+    }
+
+    /// Pieces the remote peer has.
+    pub fn peer_pieces(&self) -> &BitField {
+        &self.peer_pieces
+    }
+
+    /// Bytes per second of piece data received from the remote peer.
+    pub fn download_rate(&self) -> f64 {
+        self.download.rate(Instant::now())
+    }
+
+    /// Bytes per second of piece data sent to the remote peer.
+    pub fn upload_rate(&self) -> f64 {
+        self.upload.rate(Instant::now())
+    }
+
+    /// Extension names mapped to the extended message ids negotiated with the remote peer.
+    pub fn peer_extensions(&self) -> &HashMap<String, u8> {
+        &self.peer_extensions
     }
 
     /// Initiate the handshake to the remote peer and handle message.
     pub async fn initiate_loop(&mut self) -> Result<()> {
-        unimplemented!()
+        if !self.handshake_done {
+            self.handshake(true).await?;
+        }
+        self.message_loop().await
     }
 
     /// Accept the handshake from the remote peer and handle message.
     pub async fn accept_loop(&mut self) -> Result<()> {
-        unimplemented!()
+        if !self.handshake_done {
+            self.handshake(false).await?;
+        }
+        self.message_loop().await
+    }
+
+    /// Download the info dictionary from the remote peer with ut_metadata, see bep_0009.
+    /// The handshake is initiated if it has not been done yet.
+    pub async fn fetch_metadata(&mut self) -> Result<Info> {
+        if !self.handshake_done {
+            self.handshake(true).await?;
+        }
+        if !self.peer_extension_protocol {
+            return Err(Error::ExtensionNotSupport(UT_METADATA));
+        }
+        while !self.peer_extended_handshake_done {
+            let message = self.next_message().await?;
+            self.handle_message(message).await?;
+        }
+        let (extended_id, total_size) = match (
+            self.peer_extensions.get(UT_METADATA),
+            self.peer_metadata_size,
+        ) {
+            (Some(extended_id), Some(total_size)) => (*extended_id, total_size),
+            _ => return Err(Error::ExtensionNotSupport(UT_METADATA)),
+        };
+
+        let mut assembler = MetadataAssembler::new(total_size)?;
+        for piece in 0..assembler.piece_count() {
+            let message = Message::Extended {
+                extended_id,
+                payload: MetadataMessage::Request { piece }.to_payload()?,
+            };
+            self.conn.send(message).await?;
+        }
+        while !assembler.is_complete() {
+            match self.next_message().await? {
+                Message::Extended {
+                    extended_id: LOCAL_UT_METADATA_ID,
+                    payload,
+                } => match MetadataMessage::from_payload(&payload)? {
+                    MetadataMessage::Data { piece, data, .. } => assembler.insert(piece, data)?,
+                    MetadataMessage::Reject { piece } => {
+                        return Err(Error::MetadataRejected(piece))
+                    }
+                    request => self.handle_metadata_message(request).await?,
+                },
+                message => self.handle_message(message).await?,
+            }
+        }
+        let info = assembler.finish(&self.info_hash)?;
+        self.set_piece_count(info.pieces.0.len())?;
+        Ok(info)
     }
 
     /// The initiator of a connection is expected to send handshake.
     /// The recipient may wait for the initiator's handshake.
     async fn handshake(&mut self, is_initated: bool) -> Result<()> {
-        let mut framed = Framed::new(&mut self.conn, HandshakeMessageCodec);
         if is_initated {
-            let message = HandshakeMessage::new(self.info_hash.clone(), self.id.clone());
-            framed.send(message).await?;
+            self.send_handshake().await?;
         }
-        if let Some(message) = framed.next().await {
-            let message = message?;
-            if message.info_hash != self.info_hash {
-                return Err(Error::InvaildInfoHash);
-            }
-            self.peer_id = message.peer_id.clone();
-        } else {
-            return Err(Error::MessageEndUnexpected);
+        // read exactly the handshake so that messages following it stay in the connection
+        let mut buf = BytesMut::from(&[0; HANDSHAKE_LEN][..]);
+        self.conn.deref_mut().read_exact(&mut buf).await?;
+        let message = HandshakeMessageCodec
+            .decode(&mut buf)?
+            .ok_or(Error::MessageEndUnexpected)?;
+        if message.info_hash != self.info_hash {
+            return Err(Error::InvaildInfoHash);
         }
+        self.peer_id = message.peer_id.clone();
+        self.peer_extension_protocol = message.supports_extension_protocol();
+        self.peer_fast_extension = message.supports_fast_extension();
+        let peer_dht = message.supports_dht();
 
         if !is_initated {
-            let message = HandshakeMessage::new(self.info_hash.clone(), self.id.clone());
-            framed.send(message).await?;
+            self.send_handshake().await?;
+        }
+        self.handshake_done = true;
+        if self.peer_extension_protocol {
+            self.send_extended_handshake().await?;
+        }
+        if let (Some(dht), true) = (&self.dht, peer_dht) {
+            let port = dht.port;
+            self.conn.send(Message::Port { port }).await?;
+        }
+        Ok(())
+    }
+
+    async fn send_extended_handshake(&mut self) -> Result<()> {
+        let mut handshake = ExtendedHandshake {
+            reqq: Some(self.max_request_queue_len),
+            ..Default::default()
+        };
+        handshake
+            .m
+            .insert(UT_METADATA.to_string(), LOCAL_UT_METADATA_ID);
+        handshake.m.insert(UT_PEX.to_string(), LOCAL_UT_PEX_ID);
+        let message = Message::Extended {
+            extended_id: EXTENDED_HANDSHAKE_ID,
+            payload: handshake.to_payload()?,
+        };
+        self.conn.send(message).await
+    }
+
+    async fn send_handshake(&mut self) -> Result<()> {
+        let mut message = HandshakeMessage::new(self.info_hash.clone(), self.id.clone());
+        if self.dht.is_some() {
+            message.set_dht();
+        }
+        let mut buf = BytesMut::new();
+        HandshakeMessageCodec.encode(message, &mut buf)?;
+        let conn = self.conn.deref_mut();
+        conn.write_all(&buf).await?;
+        conn.flush().await?;
+        Ok(())
+    }
+
+    async fn next_message(&mut self) -> Result<Message> {
+        match self.conn.next().await {
+            Some(message) => message,
+            None => Err(Error::MessageEndUnexpected),
+        }
+    }
+
+    /// Handle commands and messages from the remote peer until shutdown.
+    async fn message_loop(&mut self) -> Result<()> {
+        loop {
+            self.send_pex().await?;
+            let next = {
+                // wakes the loop up to send the held back ut_pex message
+                let pex_delay = self.pex_delay();
+                let cmd = self.cmd_rx.recv();
+                let message = self.conn.next();
+                let pex_timer = async move {
+                    match pex_delay {
+                        Some(delay) => task::sleep(delay).await,
+                        None => future::pending().await,
+                    }
+                };
+                pin_mut!(cmd, message, pex_timer);
+                match future::select(cmd, future::select(message, pex_timer)).await {
+                    Either::Left((cmd, _)) => Wakeup::Command(cmd.ok()),
+                    Either::Right((Either::Left((message, _)), _)) => Wakeup::Message(message),
+                    Either::Right((Either::Right(_), _)) => Wakeup::PexDue,
+                }
+            };
+            match next {
+                // all senders are dropped, nobody can drive the session anymore
+                Wakeup::Command(None) | Wakeup::Command(Some(Command::Shutdown)) => break,
+                Wakeup::Command(Some(cmd)) => self.handle_command(cmd).await?,
+                Wakeup::Message(Some(message)) => self.handle_message(message?).await?,
+                Wakeup::Message(None) => return Err(Error::MessageEndUnexpected),
+                // the ut_pex message is sent at the top of the loop
+                Wakeup::PexDue => {}
+            }
+        }
+        self.conn.close().await?;
+        Ok(())
+    }
+
+    async fn handle_command(&mut self, cmd: Command) -> Result<()> {
+        match cmd {
+            Command::RequestPiece {
+                piece_index,
+                block_begin,
+                block_length,
+            } => {
+                self.pending_requests.push_back(BlockRequest {
+                    piece_index,
+                    block_begin,
+                    block_length,
+                });
+                self.send_requests().await?;
+            }
+            Command::CancelPiece {
+                piece_index,
+                block_begin,
+                block_length,
+            } => {
+                let request = BlockRequest {
+                    piece_index,
+                    block_begin,
+                    block_length,
+                };
+                self.pending_requests.retain(|pending| *pending != request);
+                if self
+                    .remove_in_flight(piece_index, block_begin, block_length)
+                    .is_some()
+                {
+                    self.conn
+                        .send(Message::Cancel {
+                            piece_index,
+                            block_begin,
+                            block_length,
+                        })
+                        .await?;
+                    self.send_requests().await?;
+                }
+            }
+            Command::SendPiece {
+                piece_index,
+                block_begin,
+                block_data,
+            } => {
+                self.upload.record(block_data.len(), Instant::now());
+                self.conn
+                    .send(Message::Piece {
+                        piece_index,
+                        block_begin,
+                        block_data,
+                    })
+                    .await?;
+            }
+            Command::Choke => {
+                self.am_choking = true;
+                self.conn.send(Message::Choke).await?;
+            }
+            Command::Unchoke => {
+                self.am_choking = false;
+                self.conn.send(Message::UnChoke).await?;
+            }
+            Command::SetInterested(interested) => {
+                self.am_interested = interested;
+                let message = if interested {
+                    Message::Intersted
+                } else {
+                    Message::NotInterested
+                };
+                self.conn.send(message).await?;
+            }
+            Command::SendHave(piece_index) => {
+                self.conn.send(Message::Have { piece_index }).await?;
+            }
+            Command::SendPex(message) => {
+                self.pending_pex.merge(message);
+                self.send_pex().await?;
+            }
+            Command::SuggestPiece(piece_index) => {
+                self.send_fast(Message::SuggestPiece { piece_index })
+                    .await?;
+            }
+            Command::AllowFast(piece_index) => {
+                self.send_fast(Message::AllowedFast { piece_index }).await?;
+            }
+            Command::Shutdown => unreachable!("shutdown is handled by the message loop"),
+        }
+        Ok(())
+    }
+
+    async fn handle_message(&mut self, message: Message) -> Result<()> {
+        match message {
+            Message::Choke => {
+                if !self.peer_choking {
+                    self.send_event(SessionEvent::Choked).await;
+                }
+                self.peer_choking = true;
+                // without the fast extension a choke discards every unanswered request,
+                // so they are queued again to be sent once unchoked
+                if !self.peer_fast_extension {
+                    while let Some(request) = self.in_flight_requests.pop_back() {
+                        self.pending_requests.push_front(request);
+                    }
+                }
+            }
+            Message::Piece {
+                piece_index,
+                block_begin,
+                block_data,
+            } => {
+                self.download.record(block_data.len(), Instant::now());
+                // a block that is not requested, or already cancelled, is dropped
+                if self
+                    .remove_in_flight(piece_index, block_begin, block_data.len())
+                    .is_some()
+                {
+                    self.send_event(SessionEvent::BlockReceived {
+                        piece_index,
+                        block_begin,
+                        block_data,
+                    })
+                    .await;
+                }
+                self.send_requests().await?;
+            }
+            Message::RejectRequest {
+                piece_index,
+                block_begin,
+                block_length,
+            } => {
+                if let Some(request) = self.remove_in_flight(piece_index, block_begin, block_length)
+                {
+                    self.pending_requests.push_front(request);
+                }
+                self.send_requests().await?;
+            }
+            Message::UnChoke => {
+                if self.peer_choking {
+                    self.send_event(SessionEvent::Unchoked).await;
+                }
+                self.peer_choking = false;
+                self.send_requests().await?;
+            }
+            Message::Intersted | Message::NotInterested => {
+                let interested = message == Message::Intersted;
+                if self.peer_interested != interested {
+                    self.send_event(SessionEvent::Interested(interested)).await;
+                }
+                self.peer_interested = interested;
+            }
+            Message::Have { piece_index } => {
+                // the index comes from the remote peer, it must not grow the bitfield unbounded
+                let piece_count = match self.piece_count {
+                    0 => MAX_PIECE_COUNT,
+                    piece_count => piece_count,
+                };
+                if piece_index >= piece_count {
+                    return Err(Error::InvalidPiece(piece_index));
+                }
+                self.peer_pieces.set(piece_index);
+            }
+            Message::Port { port } => {
+                if let Some(dht) = &self.dht {
+                    // the receiver may have gone, which only means the DHT node is stopped
+                    let _ = dht.dht_tx.send(SocketAddr::new(dht.peer_ip, port)).await;
+                }
+            }
+            Message::BitField { bitfield } => {
+                self.peer_have_all = false;
+                self.peer_pieces = if self.piece_count != 0 {
+                    BitField::from_bytes(bitfield.as_bytes(), self.piece_count)?
+                } else {
+                    bitfield
+                };
+            }
+            Message::HaveAll => {
+                self.peer_have_all = true;
+                self.peer_pieces = BitField::full(self.piece_count);
+            }
+            Message::HaveNone => {
+                self.peer_have_all = false;
+                self.peer_pieces = BitField::new(self.piece_count);
+            }
+            Message::Request {
+                piece_index,
+                block_begin,
+                block_length,
+            } if self.am_choking => {
+                // without the fast extension requests of a choked peer are silently dropped
+                let message = Message::RejectRequest {
+                    piece_index,
+                    block_begin,
+                    block_length,
+                };
+                self.send_fast(message).await?;
+            }
+            Message::Request {
+                piece_index,
+                block_begin,
+                block_length,
+            } => {
+                self.send_event(SessionEvent::BlockRequested {
+                    piece_index,
+                    block_begin,
+                    block_length,
+                })
+                .await;
+            }
+            Message::Cancel {
+                piece_index,
+                block_begin,
+                block_length,
+            } => {
+                self.send_event(SessionEvent::BlockCancelled {
+                    piece_index,
+                    block_begin,
+                    block_length,
+                })
+                .await;
+            }
+            Message::Extended {
+                extended_id: EXTENDED_HANDSHAKE_ID,
+                payload,
+            } => {
+                let handshake = ExtendedHandshake::from_payload(&payload)?;
+                handshake.merge_into(&mut self.peer_extensions);
+                if handshake.metadata_size.is_some() {
+                    self.peer_metadata_size = handshake.metadata_size;
+                }
+                self.peer_extended_handshake_done = true;
+            }
+            Message::Extended {
+                extended_id: LOCAL_UT_METADATA_ID,
+                payload,
+            } => {
+                let message = MetadataMessage::from_payload(&payload)?;
+                self.handle_metadata_message(message).await?;
+            }
+            Message::Extended {
+                extended_id: LOCAL_UT_PEX_ID,
+                payload,
+            } => {
+                let message = PexMessage::from_payload(&payload)?;
+                if let Some(pex_tx) = &self.pex_tx {
+                    let peers = message.added.into_iter().map(|peer| peer.address).collect();
+                    // the receiver may have gone, which only means nobody is interested anymore
+                    let _ = pex_tx.send(peers).await;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Local doesn't serve metadata, so every request of the remote peer is rejected.
+    async fn handle_metadata_message(&mut self, message: MetadataMessage) -> Result<()> {
+        if let MetadataMessage::Request { piece } = message {
+            if let Some(extended_id) = self.peer_extensions.get(UT_METADATA) {
+                let message = Message::Extended {
+                    extended_id: *extended_id,
+                    payload: MetadataMessage::Reject { piece }.to_payload()?,
+                };
+                self.conn.send(message).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn send_event(&self, event: SessionEvent) {
+        if let Some(event_tx) = &self.event_tx {
+            // the receiver may have gone, which only means nobody is interested anymore
+            let _ = event_tx.send(event).await;
+        }
+    }
+
+    /// Send a message of the fast extension, dropped if the remote peer doesn't support it.
+    async fn send_fast(&mut self, message: Message) -> Result<()> {
+        if self.peer_fast_extension {
+            self.conn.send(message).await?;
+        }
+        Ok(())
+    }
+
+    /// Send the pending swarm changes, at most one ut_pex message per minute.
+    async fn send_pex(&mut self) -> Result<()> {
+        if self.pending_pex.is_empty() {
+            return Ok(());
+        }
+        let extended_id = match self.peer_extensions.get(UT_PEX) {
+            Some(extended_id) => *extended_id,
+            None => return Ok(()),
+        };
+        if let Some(sent_at) = self.pex_sent_at {
+            if sent_at.elapsed() < PEX_INTERVAL {
+                return Ok(());
+            }
+        }
+        let message = std::mem::take(&mut self.pending_pex);
+        self.conn
+            .send(Message::Extended {
+                extended_id,
+                payload: message.to_payload()?,
+            })
+            .await?;
+        self.pex_sent_at = Some(Instant::now());
+        Ok(())
+    }
+
+    /// Time left until the held back ut_pex message can be sent, `None` if there is none.
+    fn pex_delay(&self) -> Option<Duration> {
+        if self.pending_pex.is_empty() || !self.peer_extensions.contains_key(UT_PEX) {
+            return None;
+        }
+        let sent_at = self.pex_sent_at?;
+        Some(PEX_INTERVAL.saturating_sub(sent_at.elapsed()))
+    }
+
+    /// Send the queued requests unless the remote peer is choking us,
+    /// keeping at most `max_request_queue_len` of them in flight.
+    async fn send_requests(&mut self) -> Result<()> {
+        if self.peer_choking {
+            return Ok(());
+        }
+        while self.in_flight_requests.len() < self.max_request_queue_len {
+            let request = match self.pending_requests.pop_front() {
+                Some(request) => request,
+                None => break,
+            };
+            self.conn
+                .send(Message::Request {
+                    piece_index: request.piece_index,
+                    block_begin: request.block_begin,
+                    block_length: request.block_length,
+                })
+                .await?;
+            self.in_flight_requests.push_back(request);
+        }
+        Ok(())
+    }
+
+    fn remove_in_flight(
+        &mut self,
+        piece_index: usize,
+        block_begin: usize,
+        block_length: usize,
+    ) -> Option<BlockRequest> {
+        let request = BlockRequest {
+            piece_index,
+            block_begin,
+            block_length,
+        };
+        let index = self
+            .in_flight_requests
+            .iter()
+            .position(|in_flight| *in_flight == request)?;
+        self.in_flight_requests.remove(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peer_protocol::metadata::{MAX_METADATA_SIZE, METADATA_PIECE_LEN};
+    use crate::peer_protocol::pex::PexPeer;
+    use async_std::channel::unbounded;
+    use async_std::net::{TcpListener, TcpStream};
+    use async_std::task::{block_on, spawn, JoinHandle};
+    use bytes::Bytes;
+    use serde_bencode::{de::from_bytes, ser::to_bytes};
+    use std::net::SocketAddr;
+
+    /// Connect to the session listening on `addr` with `handshake`,
+    /// returning the handshake of the session.
+    async fn connect_remote_with(
+        addr: SocketAddr,
+        handshake: HandshakeMessage,
+    ) -> (Framed<TcpStream, MessageCodec>, HandshakeMessage) {
+        let mut conn = TcpStream::connect(addr).await.unwrap();
+        let mut buf = BytesMut::new();
+        HandshakeMessageCodec.encode(handshake, &mut buf).unwrap();
+        conn.write_all(&buf).await.unwrap();
+        let mut buf = BytesMut::from(&[0; HANDSHAKE_LEN][..]);
+        conn.read_exact(&mut buf).await.unwrap();
+        let handshake = HandshakeMessageCodec.decode(&mut buf).unwrap().unwrap();
+        (Framed::new(conn, MessageCodec), handshake)
+    }
+
+    /// Spawn a session accepting a connection, configured by `setup` with the address of the
+    /// remote peer, and connect to it with `handshake`.
+    /// Returns the remote end once the session has sent its extended handshake,
+    /// the handshake of the session, the command channel and the session task.
+    async fn spawn_session_with<F>(
+        handshake: HandshakeMessage,
+        setup: F,
+    ) -> (
+        Framed<TcpStream, MessageCodec>,
+        HandshakeMessage,
+        Sender<Command>,
+        JoinHandle<Result<Session<TcpStream>>>,
+    )
+    where
+        F: FnOnce(&mut Session<TcpStream>, SocketAddr) + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (cmd_tx, cmd_rx) = unbounded();
+        let info_hash = handshake.info_hash.clone();
+        let handle = spawn(async move {
+            let (conn, peer_addr) = listener.accept().await.unwrap();
+            let mut session =
+                Session::with_client_prefix(conn, cmd_rx, info_hash, metainfo::CLIENT_PREFIX);
+            setup(&mut session, peer_addr);
+            session.accept_loop().await.map(|_| session)
+        });
+
+        let (mut remote, session_handshake) = connect_remote_with(addr, handshake).await;
+        assert!(session_handshake
+            .peer_id
+            .as_ref()
+            .starts_with(metainfo::CLIENT_PREFIX));
+        match remote.next().await.unwrap().unwrap() {
+            Message::Extended {
+                extended_id,
+                payload,
+            } => {
+                assert_eq!(extended_id, EXTENDED_HANDSHAKE_ID);
+                assert!(ExtendedHandshake::from_payload(&payload).is_ok());
+            }
+            message => panic!("unexpected message {:?}", message),
         }
-        return Ok(());
+        (remote, session_handshake, cmd_tx, handle)
+    }
+
+    /// Same as [`spawn_session_with`], connecting with a default handshake.
+    async fn spawn_session<F>(
+        setup: F,
+    ) -> (
+        Framed<TcpStream, MessageCodec>,
+        Sender<Command>,
+        JoinHandle<Result<Session<TcpStream>>>,
+    )
+    where
+        F: FnOnce(&mut Session<TcpStream>, SocketAddr) + Send + 'static,
+    {
+        let handshake = HandshakeMessage::new(
+            metainfo::HashPiece::rand_new(),
+            metainfo::HashPiece::peer_id(metainfo::CLIENT_PREFIX),
+        );
+        let (remote, _, cmd_tx, handle) = spawn_session_with(handshake, setup).await;
+        (remote, cmd_tx, handle)
+    }
+
+    /// Accept the session connecting to `listener` and complete the handshake.
+    async fn accept_remote(
+        listener: TcpListener,
+        info_hash: metainfo::HashPiece,
+    ) -> Framed<TcpStream, MessageCodec> {
+        let (mut conn, _) = listener.accept().await.unwrap();
+        let mut buf = BytesMut::from(&[0; HANDSHAKE_LEN][..]);
+        conn.read_exact(&mut buf).await.unwrap();
+        assert!(HandshakeMessageCodec.decode(&mut buf).unwrap().is_some());
+        let mut buf = BytesMut::new();
+        HandshakeMessageCodec
+            .encode(
+                HandshakeMessage::new(info_hash, metainfo::HashPiece::rand_new()),
+                &mut buf,
+            )
+            .unwrap();
+        conn.write_all(&buf).await.unwrap();
+        Framed::new(conn, MessageCodec)
+    }
+
+    /// Serve `metadata` with ut_metadata, rejecting every request if `reject` is set.
+    /// The messages of `announce` are sent before the metadata.
+    async fn serve_metadata(
+        listener: TcpListener,
+        info_hash: metainfo::HashPiece,
+        metadata: Vec<u8>,
+        reject: bool,
+        announce: Vec<Message>,
+    ) {
+        let mut remote = accept_remote(listener, info_hash).await;
+        for message in announce {
+            remote.send(message).await.unwrap();
+        }
+        let session_metadata_id = match remote.next().await.unwrap().unwrap() {
+            Message::Extended { payload, .. } => {
+                ExtendedHandshake::from_payload(&payload).unwrap().m[UT_METADATA]
+            }
+            message => panic!("unexpected message {:?}", message),
+        };
+        let mut handshake = ExtendedHandshake {
+            metadata_size: Some(metadata.len()),
+            ..Default::default()
+        };
+        handshake.m.insert(UT_METADATA.to_string(), 3);
+        remote
+            .send(Message::Extended {
+                extended_id: EXTENDED_HANDSHAKE_ID,
+                payload: handshake.to_payload().unwrap(),
+            })
+            .await
+            .unwrap();
+        while let Some(Ok(Message::Extended {
+            extended_id,
+            payload,
+        })) = remote.next().await
+        {
+            assert_eq!(extended_id, 3);
+            let piece = match MetadataMessage::from_payload(&payload).unwrap() {
+                MetadataMessage::Request { piece } => piece,
+                message => panic!("unexpected message {:?}", message),
+            };
+            let response = if reject {
+                MetadataMessage::Reject { piece }
+            } else {
+                let begin = piece * METADATA_PIECE_LEN;
+                let end = metadata.len().min(begin + METADATA_PIECE_LEN);
+                MetadataMessage::Data {
+                    piece,
+                    total_size: metadata.len(),
+                    data: Bytes::copy_from_slice(&metadata[begin..end]),
+                }
+            };
+            remote
+                .send(Message::Extended {
+                    extended_id: session_metadata_id,
+                    payload: response.to_payload().unwrap(),
+                })
+                .await
+                .unwrap();
+        }
+    }
+
+    fn example_metadata() -> (Info, Vec<u8>) {
+        let raw_torrent =
+            include_bytes!("../magnet/example/debian-11.0.0-amd64-netinst.iso.torrent");
+        let meta_info = from_bytes::<metainfo::MetaInfo>(raw_torrent).unwrap();
+        let metadata = to_bytes(&meta_info.info).unwrap();
+        (meta_info.info, metadata)
+    }
+
+    /// Fetch the metadata from a remote peer sending `announce` first,
+    /// returns the metadata with the pieces of the remote peer.
+    async fn fetch_metadata(
+        info_hash: metainfo::HashPiece,
+        metadata: Vec<u8>,
+        reject: bool,
+        announce: Vec<Message>,
+    ) -> Result<(Info, BitField)> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        spawn(serve_metadata(
+            listener,
+            info_hash.clone(),
+            metadata,
+            reject,
+            announce,
+        ));
+        let (_cmd_tx, cmd_rx) = unbounded();
+        let conn = TcpStream::connect(addr).await.unwrap();
+        let mut session =
+            Session::with_client_prefix(conn, cmd_rx, info_hash, metainfo::CLIENT_PREFIX);
+        session
+            .fetch_metadata()
+            .await
+            .map(|info| (info, session.peer_pieces().clone()))
+    }
+
+    #[test]
+    fn test_fetch_metadata() {
+        let (info, metadata) = example_metadata();
+        assert!(metadata.len() > METADATA_PIECE_LEN);
+        let info_hash = metainfo::HashPiece::from(metadata.as_slice());
+        block_on(async {
+            let fetched = fetch_metadata(info_hash.clone(), metadata.clone(), false, vec![]).await;
+            assert_eq!(fetched.unwrap().0, info);
+
+            let rejected = fetch_metadata(info_hash, metadata.clone(), true, vec![]).await;
+            assert!(matches!(rejected, Err(Error::MetadataRejected(_))));
+
+            let mismatched =
+                fetch_metadata(metainfo::HashPiece::rand_new(), metadata, false, vec![]).await;
+            assert!(matches!(mismatched, Err(Error::MetadataHashMismatch)));
+        });
+    }
+
+    #[test]
+    fn test_fetch_metadata_bitfield() {
+        let (info, metadata) = example_metadata();
+        let info_hash = metainfo::HashPiece::from(metadata.as_slice());
+        let piece_count = info.pieces.0.len();
+        assert_ne!(piece_count % 8, 0);
+        block_on(async {
+            // the bitfield arrives before the piece count is known
+            let mut bytes = vec![0; BitField::byte_len(piece_count)];
+            bytes[0] = 0b1000_0000;
+            let bitfield = BitField::from_raw(&bytes);
+            let announce = vec![
+                Message::BitField { bitfield },
+                Message::Have {
+                    piece_index: piece_count - 1,
+                },
+            ];
+            let (_, pieces) = fetch_metadata(info_hash.clone(), metadata.clone(), false, announce)
+                .await
+                .unwrap();
+            assert_eq!(pieces.len(), piece_count);
+            assert_eq!(pieces.count_set(), 2);
+            assert!(pieces.has(0));
+            assert!(pieces.has(piece_count - 1));
+
+            // a seed stays a seed once the piece count is known
+            let announce = vec![Message::HaveAll];
+            let (_, pieces) = fetch_metadata(info_hash.clone(), metadata.clone(), false, announce)
+                .await
+                .unwrap();
+            assert_eq!(pieces.len(), piece_count);
+            assert!(pieces.is_complete(piece_count));
+            let announce = vec![Message::HaveAll, Message::HaveNone];
+            let (_, pieces) = fetch_metadata(info_hash.clone(), metadata.clone(), false, announce)
+                .await
+                .unwrap();
+            assert_eq!(pieces, BitField::new(piece_count));
+
+            // a spare bit is set
+            *bytes.last_mut().unwrap() |= 1;
+            let bitfield = BitField::from_raw(&bytes);
+            let announce = vec![Message::BitField { bitfield }];
+            let fetched = fetch_metadata(info_hash, metadata, false, announce).await;
+            assert!(matches!(fetched, Err(Error::InvalidBitField(count)) if count == piece_count));
+        });
+    }
+
+    #[test]
+    fn test_fetch_metadata_oversized() {
+        let info_hash = metainfo::HashPiece::rand_new();
+        block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let remote_info_hash = info_hash.clone();
+            let remote = spawn(async move {
+                let mut remote = accept_remote(listener, remote_info_hash).await;
+                assert!(matches!(
+                    remote.next().await.unwrap().unwrap(),
+                    Message::Extended { .. }
+                ));
+                let mut handshake = ExtendedHandshake {
+                    metadata_size: Some(MAX_METADATA_SIZE + 1),
+                    ..Default::default()
+                };
+                handshake.m.insert(UT_METADATA.to_string(), 3);
+                remote
+                    .send(Message::Extended {
+                        extended_id: EXTENDED_HANDSHAKE_ID,
+                        payload: handshake.to_payload().unwrap(),
+                    })
+                    .await
+                    .unwrap();
+                // no metadata piece is requested
+                remote.next().await.is_none()
+            });
+            let (_cmd_tx, cmd_rx) = unbounded();
+            let conn = TcpStream::connect(addr).await.unwrap();
+            let mut session =
+                Session::with_client_prefix(conn, cmd_rx, info_hash, metainfo::CLIENT_PREFIX);
+            assert!(matches!(
+                session.fetch_metadata().await,
+                Err(Error::InvalidMetadataSize(size)) if size == MAX_METADATA_SIZE + 1
+            ));
+            drop(session);
+            assert!(remote.await);
+        });
+    }
+
+    #[test]
+    fn test_session_pex() {
+        block_on(async {
+            let (pex_tx, pex_rx) = unbounded();
+            let (mut remote, cmd_tx, handle) =
+                spawn_session(|session, _| session.set_pex_sender(pex_tx)).await;
+            let mut handshake = ExtendedHandshake::default();
+            handshake.m.insert(UT_PEX.to_string(), 5);
+            remote
+                .send(Message::Extended {
+                    extended_id: EXTENDED_HANDSHAKE_ID,
+                    payload: handshake.to_payload().unwrap(),
+                })
+                .await
+                .unwrap();
+
+            let peer = |addr: &str| PexPeer {
+                address: PeerAddress(addr.parse().unwrap()),
+                flags: 0,
+            };
+            let message = PexMessage {
+                added: vec![peer("1.2.3.4:1234"), peer("[2001:db8::1]:6881")],
+                dropped: vec![],
+            };
+            remote
+                .send(Message::Extended {
+                    extended_id: LOCAL_UT_PEX_ID,
+                    payload: message.to_payload().unwrap(),
+                })
+                .await
+                .unwrap();
+            assert_eq!(
+                pex_rx.recv().await.unwrap(),
+                vec![
+                    PeerAddress("1.2.3.4:1234".parse().unwrap()),
+                    PeerAddress("[2001:db8::1]:6881".parse().unwrap())
+                ]
+            );
+
+            let sent = PexMessage {
+                added: vec![peer("5.6.7.8:6881")],
+                dropped: vec![],
+            };
+            cmd_tx.send(Command::SendPex(sent.clone())).await.unwrap();
+            assert_eq!(
+                remote.next().await.unwrap().unwrap(),
+                Message::Extended {
+                    extended_id: 5,
+                    payload: sent.to_payload().unwrap(),
+                }
+            );
+            // a second ut_pex message within a minute is held back
+            cmd_tx
+                .send(Command::SendPex(PexMessage {
+                    added: vec![peer("9.9.9.9:6881")],
+                    dropped: vec![],
+                }))
+                .await
+                .unwrap();
+            cmd_tx.send(Command::SendHave(1)).await.unwrap();
+            assert_eq!(
+                remote.next().await.unwrap().unwrap(),
+                Message::Have { piece_index: 1 }
+            );
+
+            cmd_tx.send(Command::Shutdown).await.unwrap();
+            assert!(handle.await.is_ok());
+        });
+    }
+
+    #[test]
+    fn test_session_pex_held_back() {
+        block_on(async {
+            // the last ut_pex message was sent almost a minute ago
+            let (mut remote, cmd_tx, handle) = spawn_session(|session, _| {
+                let elapsed = PEX_INTERVAL - Duration::from_millis(200);
+                session.pex_sent_at = Instant::now().checked_sub(elapsed);
+            })
+            .await;
+            let mut handshake = ExtendedHandshake::default();
+            handshake.m.insert(UT_PEX.to_string(), 5);
+            remote
+                .send(Message::Extended {
+                    extended_id: EXTENDED_HANDSHAKE_ID,
+                    payload: handshake.to_payload().unwrap(),
+                })
+                .await
+                .unwrap();
+            let message = PexMessage {
+                added: vec![PexPeer {
+                    address: PeerAddress("5.6.7.8:6881".parse().unwrap()),
+                    flags: 0,
+                }],
+                dropped: vec![],
+            };
+            let started_at = Instant::now();
+            cmd_tx
+                .send(Command::SendPex(message.clone()))
+                .await
+                .unwrap();
+            // the message is sent once the minute is over, without any other traffic
+            assert_eq!(
+                remote.next().await.unwrap().unwrap(),
+                Message::Extended {
+                    extended_id: 5,
+                    payload: message.to_payload().unwrap(),
+                }
+            );
+            assert!(started_at.elapsed() >= Duration::from_millis(100));
+
+            cmd_tx.send(Command::Shutdown).await.unwrap();
+            assert!(handle.await.is_ok());
+        });
+    }
+
+    #[test]
+    fn test_session_fast_extension() {
+        block_on(async {
+            let (mut remote, cmd_tx, handle) =
+                spawn_session(|session, _| session.set_piece_count(10).unwrap()).await;
+            remote.send(Message::HaveAll).await.unwrap();
+            let request = Message::Request {
+                piece_index: 1,
+                block_begin: 0,
+                block_length: 16384,
+            };
+            remote.send(request).await.unwrap();
+            // the session chokes the remote peer, so the request is rejected
+            assert_eq!(
+                remote.next().await.unwrap().unwrap(),
+                Message::RejectRequest {
+                    piece_index: 1,
+                    block_begin: 0,
+                    block_length: 16384,
+                }
+            );
+            cmd_tx.send(Command::SuggestPiece(3)).await.unwrap();
+            assert_eq!(
+                remote.next().await.unwrap().unwrap(),
+                Message::SuggestPiece { piece_index: 3 }
+            );
+            cmd_tx.send(Command::Shutdown).await.unwrap();
+            let session = handle.await.unwrap();
+            assert_eq!(session.peer_pieces().len(), 10);
+            assert!(session.peer_pieces().is_complete(10));
+        });
+    }
+
+    #[test]
+    fn test_session_have_out_of_range() {
+        block_on(async {
+            let (mut remote, _cmd_tx, handle) =
+                spawn_session(|session, _| session.set_piece_count(10).unwrap()).await;
+            remote.send(Message::Have { piece_index: 9 }).await.unwrap();
+            remote
+                .send(Message::Have { piece_index: 10 })
+                .await
+                .unwrap();
+            assert!(matches!(handle.await, Err(Error::InvalidPiece(10))));
+
+            // without the piece count the bitfield only grows up to what a bitfield message holds
+            let (mut remote, _cmd_tx, handle) = spawn_session(|_, _| {}).await;
+            remote.send(Message::Have { piece_index: 3 }).await.unwrap();
+            remote
+                .send(Message::Have {
+                    piece_index: 0xFFFF_FFFF,
+                })
+                .await
+                .unwrap();
+            assert!(matches!(
+                handle.await,
+                Err(Error::InvalidPiece(0xFFFF_FFFF))
+            ));
+        });
+    }
+
+    #[test]
+    fn test_session_pipelining() {
+        block_on(async {
+            let (mut remote, cmd_tx, handle) = spawn_session(|_, _| {}).await;
+            let request = |piece_index| Message::Request {
+                piece_index,
+                block_begin: 0,
+                block_length: 4,
+            };
+            for piece_index in 0..20 {
+                cmd_tx
+                    .send(Command::RequestPiece {
+                        piece_index,
+                        block_begin: 0,
+                        block_length: 4,
+                    })
+                    .await
+                    .unwrap();
+            }
+            remote.send(Message::UnChoke).await.unwrap();
+            for piece_index in 0..16 {
+                assert_eq!(remote.next().await.unwrap().unwrap(), request(piece_index));
+            }
+            // the queue is full, nothing else is requested before a piece arrives
+            cmd_tx.send(Command::SendHave(0)).await.unwrap();
+            assert_eq!(
+                remote.next().await.unwrap().unwrap(),
+                Message::Have { piece_index: 0 }
+            );
+            remote
+                .send(Message::Piece {
+                    piece_index: 0,
+                    block_begin: 0,
+                    block_data: Bytes::from_static(b"data"),
+                })
+                .await
+                .unwrap();
+            assert_eq!(remote.next().await.unwrap().unwrap(), request(16));
+            cmd_tx.send(Command::SendHave(0)).await.unwrap();
+            assert_eq!(
+                remote.next().await.unwrap().unwrap(),
+                Message::Have { piece_index: 0 }
+            );
+
+            cmd_tx.send(Command::Shutdown).await.unwrap();
+            let session = handle.await.unwrap();
+            assert_eq!(session.in_flight_count(), 16);
+            assert_eq!(session.pending_requests.len(), 3);
+        });
+    }
+
+    #[test]
+    fn test_session_block_received() {
+        block_on(async {
+            let (event_tx, event_rx) = unbounded();
+            let (mut remote, cmd_tx, handle) =
+                spawn_session(|session, _| session.set_event_sender(event_tx)).await;
+            cmd_tx
+                .send(Command::RequestPiece {
+                    piece_index: 2,
+                    block_begin: 4,
+                    block_length: 4,
+                })
+                .await
+                .unwrap();
+            remote.send(Message::UnChoke).await.unwrap();
+            assert_eq!(event_rx.recv().await.unwrap(), SessionEvent::Unchoked);
+            assert!(matches!(
+                remote.next().await.unwrap().unwrap(),
+                Message::Request { .. }
+            ));
+            // a block never requested doesn't reach the consumer
+            let piece = |piece_index, block_data| Message::Piece {
+                piece_index,
+                block_begin: 4,
+                block_data: Bytes::from_static(block_data),
+            };
+            remote.send(piece(1, b"none")).await.unwrap();
+            remote.send(piece(2, b"data")).await.unwrap();
+            assert_eq!(
+                event_rx.recv().await.unwrap(),
+                SessionEvent::BlockReceived {
+                    piece_index: 2,
+                    block_begin: 4,
+                    block_data: Bytes::from_static(b"data"),
+                }
+            );
+
+            cmd_tx.send(Command::Shutdown).await.unwrap();
+            assert_eq!(handle.await.unwrap().in_flight_count(), 0);
+            assert!(event_rx.try_recv().is_err());
+        });
+    }
+
+    #[test]
+    fn test_session_peer_events() {
+        block_on(async {
+            let (event_tx, event_rx) = unbounded();
+            let (mut remote, cmd_tx, handle) =
+                spawn_session(|session, _| session.set_event_sender(event_tx)).await;
+            remote.send(Message::UnChoke).await.unwrap();
+            assert_eq!(event_rx.recv().await.unwrap(), SessionEvent::Unchoked);
+            // a request of a choked peer is rejected without reaching the consumer
+            let request = Message::Request {
+                piece_index: 1,
+                block_begin: 0,
+                block_length: 4,
+            };
+            remote.send(request.clone()).await.unwrap();
+            assert!(matches!(
+                remote.next().await.unwrap().unwrap(),
+                Message::RejectRequest { .. }
+            ));
+            assert!(event_rx.try_recv().is_err());
+
+            cmd_tx.send(Command::Unchoke).await.unwrap();
+            assert_eq!(remote.next().await.unwrap().unwrap(), Message::UnChoke);
+            remote.send(request).await.unwrap();
+            assert_eq!(
+                event_rx.recv().await.unwrap(),
+                SessionEvent::BlockRequested {
+                    piece_index: 1,
+                    block_begin: 0,
+                    block_length: 4,
+                }
+            );
+            remote
+                .send(Message::Cancel {
+                    piece_index: 1,
+                    block_begin: 0,
+                    block_length: 4,
+                })
+                .await
+                .unwrap();
+            assert_eq!(
+                event_rx.recv().await.unwrap(),
+                SessionEvent::BlockCancelled {
+                    piece_index: 1,
+                    block_begin: 0,
+                    block_length: 4,
+                }
+            );
+            // only changes of the choke state are reported
+            remote.send(Message::Choke).await.unwrap();
+            remote.send(Message::Choke).await.unwrap();
+            assert_eq!(event_rx.recv().await.unwrap(), SessionEvent::Choked);
+            // and of the interest
+            remote.send(Message::Intersted).await.unwrap();
+            remote.send(Message::Intersted).await.unwrap();
+            remote.send(Message::NotInterested).await.unwrap();
+            assert_eq!(
+                event_rx.recv().await.unwrap(),
+                SessionEvent::Interested(true)
+            );
+            assert_eq!(
+                event_rx.recv().await.unwrap(),
+                SessionEvent::Interested(false)
+            );
+
+            cmd_tx.send(Command::Shutdown).await.unwrap();
+            assert!(handle.await.is_ok());
+            assert!(event_rx.try_recv().is_err());
+        });
+    }
+
+    #[test]
+    fn test_session_cancel() {
+        block_on(async {
+            let (mut remote, cmd_tx, handle) = spawn_session(|_, _| {}).await;
+            let block = |piece_index| BlockRequest {
+                piece_index,
+                block_begin: 0,
+                block_length: 4,
+            };
+            for piece_index in 0..3 {
+                cmd_tx.send(block(piece_index).to_request()).await.unwrap();
+            }
+            // a request not sent yet is dropped silently
+            cmd_tx.send(block(2).to_cancel()).await.unwrap();
+            remote.send(Message::UnChoke).await.unwrap();
+            for piece_index in 0..2 {
+                assert_eq!(
+                    remote.next().await.unwrap().unwrap(),
+                    Message::Request {
+                        piece_index,
+                        block_begin: 0,
+                        block_length: 4,
+                    }
+                );
+            }
+            cmd_tx.send(block(0).to_cancel()).await.unwrap();
+            assert_eq!(
+                remote.next().await.unwrap().unwrap(),
+                Message::Cancel {
+                    piece_index: 0,
+                    block_begin: 0,
+                    block_length: 4,
+                }
+            );
+
+            cmd_tx.send(Command::Shutdown).await.unwrap();
+            let session = handle.await.unwrap();
+            assert_eq!(session.in_flight_requests, vec![block(1)]);
+            assert!(session.pending_requests.is_empty());
+        });
+    }
+
+    #[test]
+    fn test_session_transfer_totals() {
+        block_on(async {
+            let (mut remote, cmd_tx, handle) = spawn_session(|_, _| {}).await;
+            let piece = |len| Message::Piece {
+                piece_index: 0,
+                block_begin: 0,
+                block_data: Bytes::from(vec![1; len]),
+            };
+            let request = Message::Request {
+                piece_index: 0,
+                block_begin: 0,
+                block_length: 50,
+            };
+            remote.send(piece(100)).await.unwrap();
+            // the rejection tells the piece has been handled
+            remote.send(request).await.unwrap();
+            assert!(matches!(
+                remote.next().await.unwrap().unwrap(),
+                Message::RejectRequest { .. }
+            ));
+            cmd_tx
+                .send(Command::SendPiece {
+                    piece_index: 0,
+                    block_begin: 0,
+                    block_data: Bytes::from(vec![1; 50]),
+                })
+                .await
+                .unwrap();
+            assert_eq!(remote.next().await.unwrap().unwrap(), piece(50));
+
+            cmd_tx.send(Command::Shutdown).await.unwrap();
+            let session = handle.await.unwrap();
+            assert_eq!(session.download.total(), 100);
+            assert_eq!(session.upload.total(), 50);
+        });
+    }
+
+    #[test]
+    fn test_session_dht_port() {
+        block_on(async {
+            let (dht_tx, dht_rx) = unbounded();
+            let mut handshake = HandshakeMessage::new(
+                metainfo::HashPiece::rand_new(),
+                metainfo::HashPiece::rand_new(),
+            );
+            handshake.set_dht();
+            let (mut remote, session_handshake, cmd_tx, handle) =
+                spawn_session_with(handshake, |session, peer_addr| {
+                    session.enable_dht(6881, peer_addr.ip(), dht_tx)
+                })
+                .await;
+            assert!(session_handshake.supports_dht());
+            assert_eq!(
+                remote.next().await.unwrap().unwrap(),
+                Message::Port { port: 6881 }
+            );
+            remote.send(Message::Port { port: 7000 }).await.unwrap();
+            assert_eq!(
+                dht_rx.recv().await.unwrap(),
+                "127.0.0.1:7000".parse().unwrap()
+            );
+
+            cmd_tx.send(Command::Shutdown).await.unwrap();
+            assert!(handle.await.is_ok());
+        });
+    }
+
+    #[test]
+    fn test_session_command() {
+        block_on(async {
+            let (mut remote, cmd_tx, handle) = spawn_session(|_, _| {}).await;
+            cmd_tx
+                .send(Command::RequestPiece {
+                    piece_index: 1,
+                    block_begin: 0,
+                    block_length: 16384,
+                })
+                .await
+                .unwrap();
+            cmd_tx.send(Command::SetInterested(true)).await.unwrap();
+            // the request is queued because the remote peer still chokes the session
+            assert_eq!(remote.next().await.unwrap().unwrap(), Message::Intersted);
+
+            remote.send(Message::UnChoke).await.unwrap();
+            assert_eq!(
+                remote.next().await.unwrap().unwrap(),
+                Message::Request {
+                    piece_index: 1,
+                    block_begin: 0,
+                    block_length: 16384,
+                }
+            );
+
+            cmd_tx.send(Command::Shutdown).await.unwrap();
+            assert!(handle.await.is_ok());
+            assert!(remote.next().await.is_none());
+        });
+    }
+
+    #[test]
+    fn test_session_extended_handshake() {
+        block_on(async {
+            // the extended handshake of the session is checked by spawn_session
+            let (mut remote, cmd_tx, handle) = spawn_session(|_, _| {}).await;
+            remote
+                .send(Message::Extended {
+                    extended_id: EXTENDED_HANDSHAKE_ID,
+                    payload: Bytes::from_static(b"d1:md11:ut_metadatai3e6:ut_pexi1eee"),
+                })
+                .await
+                .unwrap();
+            // the session has no metadata to serve, it rejects with the negotiated id
+            remote
+                .send(Message::Extended {
+                    extended_id: LOCAL_UT_METADATA_ID,
+                    payload: MetadataMessage::Request { piece: 0 }.to_payload().unwrap(),
+                })
+                .await
+                .unwrap();
+            assert_eq!(
+                remote.next().await.unwrap().unwrap(),
+                Message::Extended {
+                    extended_id: 3,
+                    payload: MetadataMessage::Reject { piece: 0 }.to_payload().unwrap(),
+                }
+            );
+            cmd_tx.send(Command::Shutdown).await.unwrap();
+            let session = handle.await.unwrap();
+            assert_eq!(session.peer_extensions().get("ut_metadata"), Some(&3));
+            assert_eq!(session.peer_extensions().get("ut_pex"), Some(&1));
+        });
     }
 }
@@ -0,0 +1,197 @@
+//! This module provides a high-level entry point from a .torrent file to the swarm
+
+use crate::error::Result;
+use crate::magnet::MagnetLink;
+use crate::metainfo::{HashPiece, MetaInfo, PeerAddress};
+use crate::tracker::{self, AnnounceStats, Event};
+use async_std::future;
+use async_std::path::Path;
+use futures::future::join_all;
+use std::collections::HashSet;
+use std::time::Duration;
+use url::Url;
+
+/// Torrent wraps a parsed .torrent file.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Torrent {
+    meta_info: MetaInfo,
+}
+
+impl Torrent {
+    pub fn new(meta_info: MetaInfo) -> Self {
+        Self { meta_info }
+    }
+
+    /// Parse a .torrent file.
+    pub fn from_bytes(buf: &[u8]) -> Result<Self> {
+        Ok(Self::new(MetaInfo::from_bytes(buf)?))
+    }
+
+    /// Read and parse a .torrent file.
+    pub async fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(Self::new(MetaInfo::from_file(path).await?))
+    }
+
+    pub fn meta_info(&self) -> &MetaInfo {
+        &self.meta_info
+    }
+
+    pub fn into_meta_info(self) -> MetaInfo {
+        self.meta_info
+    }
+
+    pub fn name(&self) -> &str {
+        &self.meta_info.info.name
+    }
+
+    pub fn info_hash(&self) -> HashPiece {
+        self.meta_info.info_hash()
+    }
+
+    /// Returns the trackers of all tiers in the order they should be tried.
+    pub fn trackers(&self) -> Result<Vec<Url>> {
+        self.meta_info.get_trackers()
+    }
+
+    /// Returns the number of bytes of all the files.
+    pub fn total_size(&self) -> u64 {
+        self.meta_info.info.total_length()
+    }
+
+    pub fn to_magnet(&self) -> MagnetLink {
+        (&self.meta_info).into()
+    }
+
+    /// Announce the start of the download to every tracker at once and collect the peers they
+    /// return within `timeout`, `port` is the port local is listening on.
+    /// Trackers that fail, don't answer in time or use an unsupported scheme are skipped,
+    /// the last error is only returned if no tracker answered. Peers are not looked up in the DHT.
+    pub async fn discover_peers(
+        &self,
+        peer_id: &HashPiece,
+        port: u16,
+        timeout: Duration,
+    ) -> Result<Vec<PeerAddress>> {
+        let info_hash = self.info_hash();
+        let stats = AnnounceStats {
+            left: self.total_size(),
+            ..Default::default()
+        };
+        let announces = self.trackers()?.into_iter().map(|url| {
+            let info_hash = &info_hash;
+            async move {
+                let tracker = tracker::from_url(url)?;
+                let announce = tracker.announce(info_hash, peer_id, port, Event::Started, stats);
+                match future::timeout(timeout, announce).await {
+                    Ok(response) => response,
+                    Err(_) => Err(tracker::Error::Timeout),
+                }
+            }
+        });
+        let mut peers = Vec::new();
+        let mut seen = HashSet::new();
+        let mut answered = false;
+        let mut last_error = None;
+        for response in join_all(announces).await {
+            match response {
+                Ok(response) => {
+                    answered = true;
+                    for peer in response.peers {
+                        if seen.insert(peer.clone()) {
+                            peers.push(peer);
+                        }
+                    }
+                }
+                Err(e) => last_error = Some(e),
+            }
+        }
+        match last_error {
+            Some(e) if !answered => Err(e.into()),
+            _ => Ok(peers),
+        }
+    }
+}
+
+impl From<MetaInfo> for Torrent {
+    fn from(meta_info: MetaInfo) -> Self {
+        Self::new(meta_info)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_std::io::prelude::{ReadExt, WriteExt};
+    use async_std::net::TcpListener;
+    use async_std::task::{block_on, spawn};
+
+    #[test]
+    fn test_torrent() {
+        let raw_torrent =
+            include_bytes!("metainfo/example/archlinux-2011.08.19-netinstall-i686.iso.torrent");
+        let torrent = Torrent::from_bytes(raw_torrent).unwrap();
+        let meta_info = MetaInfo::from_bytes(raw_torrent).unwrap();
+        assert_eq!(torrent.name(), meta_info.info.name);
+        assert_eq!(torrent.info_hash(), meta_info.info_hash());
+        assert_eq!(
+            torrent.trackers().unwrap(),
+            meta_info.get_trackers().unwrap()
+        );
+        assert_eq!(torrent.total_size(), meta_info.info.length.unwrap());
+        let link = torrent.to_magnet();
+        assert_eq!(link.info_hash(), Some(&torrent.info_hash()));
+        assert_eq!(link, MagnetLink::from(meta_info));
+    }
+
+    #[test]
+    fn test_torrent_discover_peers() {
+        block_on(async {
+            // a tracker that never answers doesn't hold back the others
+            let silent = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let silent_url = format!("http://{}/announce", silent.local_addr().unwrap());
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = format!("http://{}/announce", listener.local_addr().unwrap());
+            let server = spawn(async move {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let n = stream.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                }
+                stream
+                    .write_all(
+                        b"HTTP/1.0 200 OK\r\n\r\n\
+                        d8:intervali1800e5:peers12:\x01\x02\x03\x04\x04\xd2\x01\x02\x03\x04\x04\xd2e",
+                    )
+                    .await
+                    .unwrap();
+            });
+
+            let raw_torrent = include_bytes!("metainfo/example/1.txt.torrent");
+            let mut meta_info = MetaInfo::from_bytes(raw_torrent).unwrap();
+            meta_info.announce = None;
+            meta_info.announce_list = vec![
+                vec!["wss://tracker.example.com".to_string()],
+                vec![silent_url, url],
+            ];
+            let torrent = Torrent::from(meta_info);
+            let peer_id = HashPiece::new(*b"-RS0001-123456789012");
+            let timeout = Duration::from_millis(500);
+            let peers = torrent
+                .discover_peers(&peer_id, 6881, timeout)
+                .await
+                .unwrap();
+            assert_eq!(peers, vec![PeerAddress("1.2.3.4:1234".parse().unwrap())]);
+            server.await;
+
+            let mut meta_info = torrent.into_meta_info();
+            meta_info.announce_list = vec![vec!["wss://tracker.example.com".to_string()]];
+            assert!(Torrent::from(meta_info)
+                .discover_peers(&peer_id, 6881, timeout)
+                .await
+                .is_err());
+            drop(silent);
+        });
+    }
+}
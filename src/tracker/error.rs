@@ -0,0 +1,28 @@
+use thiserror::Error;
+use url::ParseError as ParseUrlError;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+    #[error("{0}")]
+    Bencode(#[from] serde_bencode::Error),
+    #[error("{0}")]
+    ParseUrl(#[from] ParseUrlError),
+    #[error("Tracker url scheme {0} not support")]
+    SchemeNotSupport(String),
+    #[error("Invalid tracker response: {0}")]
+    InvalidResponse(&'static str),
+    #[error("Tracker responded with http status {0}")]
+    HttpStatus(u16),
+    #[error("Tracker failure: {0}")]
+    Failure(String),
+    #[error("Invalid peers in tracker response")]
+    InvalidPeers,
+    #[error("Tracker didn't respond in time")]
+    Timeout,
+    #[error("Tracker doesn't support scrape")]
+    ScrapeNotSupport,
+}
@@ -0,0 +1,493 @@
+//! Announce over HTTP, peers may be returned in the compact form defined in https://www.bittorrent.org/beps/bep_0023.html
+
+use super::error::{Error, Result};
+use super::{AnnounceResponse, AnnounceStats, Event, ScrapeStats, Tracker};
+use crate::metainfo::{from_compact_list, HashPiece, PeerAddress, ADDRESS_V4_LEN, ADDRESS_V6_LEN};
+use async_std::io::{self, prelude::*, ErrorKind};
+use async_std::net::TcpStream;
+use futures::future::BoxFuture;
+use serde::{
+    de::{Error as DeError, SeqAccess, Visitor},
+    Deserialize, Deserializer,
+};
+use serde_bencode::de::from_bytes;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::str;
+use std::time::Duration;
+use url::{ParseError as ParseUrlError, Url};
+
+/// Time a tracker has to answer a request, including the connection.
+const HTTP_TIMEOUT: Duration = Duration::from_secs(30);
+/// Largest accepted response, headers included.
+const MAX_RESPONSE_LEN: u64 = 4 * 1024 * 1024;
+
+/// Peers are a list of dictionaries, or a string of compact addresses.
+#[derive(Debug, Default)]
+struct Peers(Vec<PeerAddress>);
+
+#[derive(Debug, Deserialize)]
+struct DictPeer {
+    ip: String,
+    port: u16,
+}
+
+impl<'de> Deserialize<'de> for Peers {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct PeersVisitor;
+        impl<'de> Visitor<'de> for PeersVisitor {
+            type Value = Peers;
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("`compact peers` or `list of peers`")
+            }
+            fn visit_bytes<E>(self, v: &[u8]) -> std::result::Result<Self::Value, E>
+            where
+                E: DeError,
+            {
+                from_compact_list(v, ADDRESS_V4_LEN)
+                    .map(Peers)
+                    .ok_or_else(|| E::custom("invalid compact peers"))
+            }
+            fn visit_seq<A>(self, mut seq: A) -> std::result::Result<Self::Value, A::Error>
+            where
+                A: SeqAccess<'de>,
+            {
+                let mut peers = Vec::new();
+                while let Some(peer) = seq.next_element::<DictPeer>()? {
+                    let ip: IpAddr = peer.ip.parse().map_err(A::Error::custom)?;
+                    peers.push(PeerAddress(SocketAddr::new(ip, peer.port)));
+                }
+                Ok(Peers(peers))
+            }
+        }
+        deserializer.deserialize_any(PeersVisitor)
+    }
+}
+
+/// Bencoded response of an announce.
+#[derive(Debug, Deserialize)]
+struct RawAnnounceResponse {
+    #[serde(rename = "failure reason")]
+    failure_reason: Option<String>,
+    interval: Option<u64>,
+    #[serde(rename = "min interval")]
+    min_interval: Option<u64>,
+    complete: Option<u64>,
+    incomplete: Option<u64>,
+    #[serde(default)]
+    peers: Peers,
+    #[serde(with = "serde_bytes", default)]
+    peers6: Vec<u8>,
+}
+
+impl AnnounceResponse {
+    fn from_bencode(buf: &[u8]) -> Result<Self> {
+        let raw: RawAnnounceResponse = from_bytes(buf)?;
+        if let Some(reason) = raw.failure_reason {
+            return Err(Error::Failure(reason));
+        }
+        let interval = raw
+            .interval
+            .ok_or(Error::InvalidResponse("interval is missing"))?;
+        let mut peers = raw.peers.0;
+        peers.extend(from_compact_list(&raw.peers6, ADDRESS_V6_LEN).ok_or(Error::InvalidPeers)?);
+        Ok(AnnounceResponse {
+            interval: Duration::from_secs(interval),
+            min_interval: raw.min_interval.map(Duration::from_secs),
+            complete: raw.complete,
+            incomplete: raw.incomplete,
+            peers,
+        })
+    }
+}
+
+/// HttpTracker is a client of a `http://` tracker.
+#[derive(Debug, Clone)]
+pub struct HttpTracker {
+    url: Url,
+}
+
+impl HttpTracker {
+    pub fn new(url: Url) -> Result<Self> {
+        if url.scheme() != "http" {
+            return Err(Error::SchemeNotSupport(url.scheme().to_string()));
+        }
+        Ok(HttpTracker { url })
+    }
+}
+
+impl Tracker for HttpTracker {
+    fn announce<'a>(
+        &'a self,
+        info_hash: &'a HashPiece,
+        peer_id: &'a HashPiece,
+        port: u16,
+        event: Event,
+        stats: AnnounceStats,
+    ) -> BoxFuture<'a, Result<AnnounceResponse>> {
+        Box::pin(announce(&self.url, info_hash, peer_id, port, event, stats))
+    }
+
+    fn scrape<'a>(
+        &'a self,
+        info_hashes: &'a [HashPiece],
+    ) -> BoxFuture<'a, Result<HashMap<HashPiece, ScrapeStats>>> {
+        Box::pin(scrape(&self.url, info_hashes))
+    }
+}
+
+/// Announce the download of `info_hash` to the HTTP tracker at `url`,
+/// `port` is the port local is listening on.
+pub async fn announce(
+    url: &Url,
+    info_hash: &HashPiece,
+    peer_id: &HashPiece,
+    port: u16,
+    event: Event,
+    stats: AnnounceStats,
+) -> Result<AnnounceResponse> {
+    let mut query = url.query().map(|q| format!("{}&", q)).unwrap_or_default();
+    query.push_str(&format!(
+        "info_hash={}&peer_id={}&port={}&uploaded={}&downloaded={}&left={}&compact=1",
+        percent_encode(info_hash.as_ref()),
+        percent_encode(peer_id.as_ref()),
+        port,
+        stats.uploaded,
+        stats.downloaded,
+        stats.left
+    ));
+    let event = match event {
+        Event::None => None,
+        Event::Started => Some("started"),
+        Event::Stopped => Some("stopped"),
+        Event::Completed => Some("completed"),
+    };
+    if let Some(event) = event {
+        query.push_str(&format!("&event={}", event));
+    }
+    let mut url = url.clone();
+    url.set_query(Some(&query));
+    let body = http_get(&url).await?;
+    AnnounceResponse::from_bencode(&body)
+}
+
+/// Bencoded response of a scrape, `files` is keyed by the raw info hashes.
+#[derive(Debug, Deserialize)]
+struct RawScrapeResponse {
+    #[serde(rename = "failure reason")]
+    failure_reason: Option<String>,
+    #[serde(default)]
+    files: HashMap<HashPiece, ScrapeStats>,
+}
+
+fn parse_scrape(buf: &[u8]) -> Result<HashMap<HashPiece, ScrapeStats>> {
+    let raw: RawScrapeResponse = from_bytes(buf)?;
+    match raw.failure_reason {
+        Some(reason) => Err(Error::Failure(reason)),
+        None => Ok(raw.files),
+    }
+}
+
+/// Scrape `info_hashes` from the HTTP tracker whose announce url is `url`,
+/// the scrape url is derived as described in https://www.bittorrent.org/beps/bep_0048.html
+pub async fn scrape(
+    url: &Url,
+    info_hashes: &[HashPiece],
+) -> Result<HashMap<HashPiece, ScrapeStats>> {
+    let mut url = url.clone();
+    let last_segment = url.path().rsplit('/').next().unwrap_or_default();
+    if !last_segment.starts_with("announce") {
+        return Err(Error::ScrapeNotSupport);
+    }
+    let path = format!(
+        "{}scrape{}",
+        &url.path()[..url.path().len() - last_segment.len()],
+        &last_segment["announce".len()..]
+    );
+    url.set_path(&path);
+    let mut query: Vec<String> = url.query().map(|q| q.to_string()).into_iter().collect();
+    query.extend(
+        info_hashes
+            .iter()
+            .map(|info_hash| format!("info_hash={}", percent_encode(info_hash.as_ref()))),
+    );
+    url.set_query(Some(&query.join("&")));
+    let body = http_get(&url).await?;
+    parse_scrape(&body)
+}
+
+/// Percent-encode every byte except the unreserved characters of RFC 3986.
+fn percent_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len() * 3);
+    for byte in bytes {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(*byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Send a HTTP/1.0 GET request to `url` and return the body of the response.
+async fn http_get(url: &Url) -> Result<Vec<u8>> {
+    http_get_with(url, HTTP_TIMEOUT, MAX_RESPONSE_LEN).await
+}
+
+/// Same as [`http_get`], failing if the exchange takes longer than `timeout`
+/// or the response is larger than `max_len`.
+async fn http_get_with(url: &Url, timeout: Duration, max_len: u64) -> Result<Vec<u8>> {
+    if url.scheme() != "http" {
+        return Err(Error::SchemeNotSupport(url.scheme().to_string()));
+    }
+    let host = url.host_str().ok_or(ParseUrlError::EmptyHost)?;
+    let port = url.port_or_known_default().unwrap_or(80);
+    let mut target = url.path().to_string();
+    if let Some(query) = url.query() {
+        target.push('?');
+        target.push_str(query);
+    }
+    let request = format!(
+        "GET {} HTTP/1.0\r\nHost: {}:{}\r\nConnection: close\r\n\r\n",
+        target, host, port
+    );
+    let exchange = async {
+        let mut stream = TcpStream::connect((host, port)).await?;
+        stream.write_all(request.as_bytes()).await?;
+        let mut response = Vec::new();
+        // one byte more than allowed tells whether the response is too large
+        stream.take(max_len + 1).read_to_end(&mut response).await?;
+        Ok(response)
+    };
+    let mut response = match io::timeout(timeout, exchange).await {
+        Ok(response) => response,
+        Err(e) if e.kind() == ErrorKind::TimedOut => return Err(Error::Timeout),
+        Err(e) => return Err(e.into()),
+    };
+    if response.len() as u64 > max_len {
+        return Err(Error::InvalidResponse("http response is too large"));
+    }
+
+    let header_end = response
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or(Error::InvalidResponse("http header is incomplete"))?;
+    let status = str::from_utf8(&response[..header_end])
+        .ok()
+        .and_then(|header| header.split_whitespace().nth(1))
+        .and_then(|status| status.parse().ok())
+        .ok_or(Error::InvalidResponse("http status line is malformed"))?;
+    if status != 200 {
+        return Err(Error::HttpStatus(status));
+    }
+    Ok(response.split_off(header_end + 4))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_std::net::TcpListener;
+    use async_std::task::{block_on, spawn};
+
+    #[test]
+    fn test_http_get_limits() {
+        block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = Url::parse(&format!(
+                "http://{}/announce",
+                listener.local_addr().unwrap()
+            ))
+            .unwrap();
+            let server = spawn(async move {
+                // the first tracker never answers
+                let (silent, _) = listener.accept().await.unwrap();
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut response = b"HTTP/1.0 200 OK\r\n\r\n".to_vec();
+                response.resize(200, b'x');
+                // the client may close the connection before the whole response is written
+                let _ = stream.write_all(&response).await;
+                drop(silent);
+            });
+            assert!(matches!(
+                http_get_with(&url, Duration::from_millis(100), 100).await,
+                Err(Error::Timeout)
+            ));
+            assert!(matches!(
+                http_get_with(&url, Duration::from_secs(10), 100).await,
+                Err(Error::InvalidResponse(_))
+            ));
+            server.await;
+        });
+    }
+
+    #[test]
+    fn test_announce_response() {
+        let response = AnnounceResponse::from_bencode(
+            b"d8:completei5e10:incompletei3e8:intervali1800e12:min intervali60e\
+            5:peers12:\x01\x02\x03\x04\x04\xd2\x05\x06\x07\x08\x1a\xe1\
+            6:peers618:\x20\x01\x0d\xb8\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x01\x1a\xe1e",
+        )
+        .unwrap();
+        assert_eq!(response.interval, Duration::from_secs(1800));
+        assert_eq!(response.min_interval, Some(Duration::from_secs(60)));
+        assert_eq!(response.complete, Some(5));
+        assert_eq!(response.incomplete, Some(3));
+        assert_eq!(
+            response.peers,
+            vec![
+                PeerAddress("1.2.3.4:1234".parse().unwrap()),
+                PeerAddress("5.6.7.8:6881".parse().unwrap()),
+                PeerAddress("[2001:db8::1]:6881".parse().unwrap()),
+            ]
+        );
+
+        let response = AnnounceResponse::from_bencode(
+            b"d8:intervali900e5:peersld2:ip7:1.2.3.47:peer id20:aaaaaaaaaaaaaaaaaaaa\
+            4:porti1234eeee",
+        )
+        .unwrap();
+        assert_eq!(
+            response.peers,
+            vec![PeerAddress("1.2.3.4:1234".parse().unwrap())]
+        );
+
+        assert!(matches!(
+            AnnounceResponse::from_bencode(b"d14:failure reason9:not founde"),
+            Err(Error::Failure(reason)) if reason == "not found"
+        ));
+        assert!(AnnounceResponse::from_bencode(b"d8:intervali900e5:peers5:12345e").is_err());
+    }
+
+    #[test]
+    fn test_scrape_response() {
+        let mut raw = b"d5:filesd20:".to_vec();
+        raw.extend_from_slice(&[0xff; 20]);
+        raw.extend_from_slice(b"d8:completei5e10:downloadedi50e10:incompletei10ee20:");
+        raw.extend_from_slice(&[0x80; 20]);
+        raw.extend_from_slice(b"d8:completei1e10:downloadedi2e10:incompletei3e4:name3:abcee");
+        raw.extend_from_slice(b"5:flagsd20:min_request_intervali3600eee");
+        let files = parse_scrape(&raw).unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(
+            files[&HashPiece::new([0xff; 20])],
+            ScrapeStats {
+                complete: 5,
+                downloaded: 50,
+                incomplete: 10
+            }
+        );
+        assert_eq!(
+            files[&HashPiece::new([0x80; 20])],
+            ScrapeStats {
+                complete: 1,
+                downloaded: 2,
+                incomplete: 3
+            }
+        );
+        assert!(matches!(
+            parse_scrape(b"d14:failure reason9:not founde"),
+            Err(Error::Failure(_))
+        ));
+    }
+
+    #[test]
+    fn test_scrape() {
+        block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let server = spawn(async move {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let n = stream.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                }
+                let mut response = b"HTTP/1.1 200 OK\r\n\r\nd5:filesd20:".to_vec();
+                response.extend_from_slice(&[0xff; 20]);
+                response.extend_from_slice(b"d8:completei5e10:downloadedi50e10:incompletei10eeee");
+                stream.write_all(&response).await.unwrap();
+                String::from_utf8(request).unwrap()
+            });
+
+            let url = Url::parse(&format!("http://{}/x/announce.php?passkey=abc", addr)).unwrap();
+            let info_hashes = [HashPiece::new([0xff; 20]), HashPiece::new([0x41; 20])];
+            let files = scrape(&url, &info_hashes).await.unwrap();
+            assert_eq!(files[&info_hashes[0]].complete, 5);
+            assert!(!files.contains_key(&info_hashes[1]));
+            let request = server.await;
+            assert!(request.starts_with(&format!(
+                "GET /x/scrape.php?passkey=abc&info_hash={}&info_hash={} ",
+                "%FF".repeat(20),
+                "A".repeat(20)
+            )));
+
+            let url = Url::parse(&format!("http://{}/x/a", addr)).unwrap();
+            assert!(matches!(
+                scrape(&url, &info_hashes).await,
+                Err(Error::ScrapeNotSupport)
+            ));
+        })
+    }
+
+    #[test]
+    fn test_announce() {
+        block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = Url::parse(&format!(
+                "http://{}/announce?passkey=abc",
+                listener.local_addr().unwrap()
+            ))
+            .unwrap();
+            let server = spawn(async move {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let n = stream.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                }
+                stream
+                    .write_all(
+                        b"HTTP/1.0 200 OK\r\nContent-Type: text/plain\r\n\r\n\
+                        d8:intervali1800e5:peers6:\x01\x02\x03\x04\x04\xd2e",
+                    )
+                    .await
+                    .unwrap();
+                String::from_utf8(request).unwrap()
+            });
+
+            let mut info_hash = [0xff; 20];
+            info_hash[..3].copy_from_slice(b"a b");
+            let response = announce(
+                &url,
+                &HashPiece::new(info_hash),
+                &HashPiece::new(*b"-RS0001-123456789012"),
+                6881,
+                Event::Started,
+                AnnounceStats {
+                    uploaded: 0,
+                    downloaded: 0,
+                    left: 100,
+                },
+            )
+            .await
+            .unwrap();
+            assert_eq!(
+                response.peers,
+                vec![PeerAddress("1.2.3.4:1234".parse().unwrap())]
+            );
+
+            let request = server.await;
+            let request_line = request.lines().next().unwrap();
+            assert!(request_line.starts_with("GET /announce?passkey=abc&info_hash=a%20b%FF"));
+            assert!(request_line.contains("&peer_id=-RS0001-123456789012&port=6881"));
+            assert!(request_line.contains("&left=100&compact=1&event=started HTTP/1.0"));
+        })
+    }
+}
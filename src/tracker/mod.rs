@@ -0,0 +1,97 @@
+//! This module implements the tracker protocol defined in https://www.bittorrent.org/beps/bep_0003.html
+
+mod error;
+pub use error::Error;
+
+mod http;
+pub use http::{announce, scrape, HttpTracker};
+
+mod udp;
+pub use udp::UdpTracker;
+
+use crate::metainfo::{HashPiece, PeerAddress};
+use error::Result;
+use futures::future::BoxFuture;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::time::Duration;
+use url::Url;
+
+/// Tracker announces to a HTTP or UDP tracker the same way.
+pub trait Tracker {
+    /// Announce the download of `info_hash`, `port` is the port local is listening on.
+    fn announce<'a>(
+        &'a self,
+        info_hash: &'a HashPiece,
+        peer_id: &'a HashPiece,
+        port: u16,
+        event: Event,
+        stats: AnnounceStats,
+    ) -> BoxFuture<'a, Result<AnnounceResponse>>;
+
+    /// Query the number of seeders and leechers of `info_hashes`.
+    fn scrape<'a>(
+        &'a self,
+        info_hashes: &'a [HashPiece],
+    ) -> BoxFuture<'a, Result<HashMap<HashPiece, ScrapeStats>>>;
+}
+
+/// Create the client of the tracker at `url` according to its scheme.
+pub fn from_url(url: Url) -> Result<Box<dyn Tracker + Send + Sync>> {
+    match url.scheme() {
+        "http" => Ok(Box::new(HttpTracker::new(url)?)),
+        "udp" => Ok(Box::new(UdpTracker::new(url)?)),
+        scheme => Err(Error::SchemeNotSupport(scheme.to_string())),
+    }
+}
+
+/// Event of an announce, sent to the tracker when the download changes its state.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Event {
+    /// A regular announce
+    None,
+    /// The first announce of a download
+    Started,
+    /// The download is stopped
+    Stopped,
+    /// The download is completed
+    Completed,
+}
+
+/// Transfer statistics of the download reported to the tracker.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct AnnounceStats {
+    /// Total amount uploaded since the started event
+    pub uploaded: u64,
+    /// Total amount downloaded since the started event
+    pub downloaded: u64,
+    /// Number of bytes left to download
+    pub left: u64,
+}
+
+/// AnnounceResponse is the reply of the tracker to an announce.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct AnnounceResponse {
+    /// Time the client should wait before the next regular announce
+    pub interval: Duration,
+    /// Time the client must wait before announcing again
+    pub min_interval: Option<Duration>,
+    /// Number of seeders
+    pub complete: Option<u64>,
+    /// Number of leechers
+    pub incomplete: Option<u64>,
+    /// Peers of the torrent
+    pub peers: Vec<PeerAddress>,
+}
+
+/// Statistics of a torrent returned by a scrape.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, Deserialize)]
+#[serde(default)]
+pub struct ScrapeStats {
+    /// Number of seeders
+    pub complete: u64,
+    /// Number of times the download has been completed
+    pub downloaded: u64,
+    /// Number of leechers
+    pub incomplete: u64,
+}
@@ -0,0 +1,421 @@
+//! This module implements the UDP tracker protocol defined in https://www.bittorrent.org/beps/bep_0015.html
+
+use super::error::{Error, Result};
+use super::{AnnounceResponse, AnnounceStats, Event, ScrapeStats, Tracker};
+use crate::metainfo::{from_compact_list, HashPiece, ADDRESS_V4_LEN, ADDRESS_V6_LEN};
+use async_std::io::{self, ErrorKind};
+use async_std::net::{ToSocketAddrs, UdpSocket};
+use bytes::{Buf, BufMut};
+use futures::future::BoxFuture;
+use rand::random;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use url::{ParseError as ParseUrlError, Url};
+
+/// Magic constant identifying a connect request
+const PROTOCOL_ID: u64 = 0x0417_2710_1980;
+const ACTION_CONNECT: u32 = 0;
+const ACTION_ANNOUNCE: u32 = 1;
+const ACTION_SCRAPE: u32 = 2;
+const ACTION_ERROR: u32 = 3;
+/// Size of the header of a request: <connection_id><action><transaction_id>.
+const REQUEST_HEADER_LEN: usize = 16;
+/// Size of the header of a response: <action><transaction_id>.
+const RESPONSE_HEADER_LEN: usize = 8;
+/// A connection id may be used for one minute after it is received.
+const CONNECTION_ID_TTL: Duration = Duration::from_secs(60);
+/// A request is retransmitted after 15 * 2 ^ n seconds, n is increased up to 8.
+const MAX_RETRIES: u32 = 8;
+/// Largest payload of an UDP datagram, a response with many peers may need all of it.
+const MAX_PACKET_LEN: usize = 65507;
+
+/// UdpTracker is a client of an `udp://` tracker.
+#[derive(Debug, Clone)]
+pub struct UdpTracker {
+    url: Url,
+    /// sent with every announce so the tracker can identify local when its ip changes
+    key: u32,
+}
+
+impl UdpTracker {
+    pub fn new(url: Url) -> Result<Self> {
+        if url.scheme() != "udp" {
+            return Err(Error::SchemeNotSupport(url.scheme().to_string()));
+        }
+        Ok(UdpTracker { url, key: random() })
+    }
+
+    /// Announce the download of `info_hash`, `port` is the port local is listening on.
+    pub async fn announce(
+        &self,
+        info_hash: &HashPiece,
+        peer_id: &HashPiece,
+        port: u16,
+        event: Event,
+        stats: AnnounceStats,
+    ) -> Result<AnnounceResponse> {
+        let socket = self.socket().await?;
+        let payload = announce_payload(info_hash, peer_id, port, event, stats, self.key);
+        let body = transact(&socket, ACTION_ANNOUNCE, &payload).await?;
+        let entry_len = if socket.peer_addr()?.is_ipv6() {
+            ADDRESS_V6_LEN
+        } else {
+            ADDRESS_V4_LEN
+        };
+        parse_announce(&body, entry_len)
+    }
+
+    /// Query the number of seeders and leechers of `info_hashes`.
+    pub async fn scrape(
+        &self,
+        info_hashes: &[HashPiece],
+    ) -> Result<HashMap<HashPiece, ScrapeStats>> {
+        let socket = self.socket().await?;
+        let payload: Vec<u8> = info_hashes
+            .iter()
+            .flat_map(|info_hash| info_hash.as_ref().to_vec())
+            .collect();
+        let body = transact(&socket, ACTION_SCRAPE, &payload).await?;
+        parse_scrape(&body, info_hashes)
+    }
+
+    /// Create a socket connected to the tracker.
+    async fn socket(&self) -> Result<UdpSocket> {
+        let host = self.url.host_str().ok_or(ParseUrlError::EmptyHost)?;
+        let port = self.url.port().ok_or(ParseUrlError::InvalidPort)?;
+        let addr = (host, port)
+            .to_socket_addrs()
+            .await?
+            .next()
+            .ok_or(ParseUrlError::EmptyHost)?;
+        let socket = if addr.is_ipv6() {
+            UdpSocket::bind("[::]:0").await?
+        } else {
+            UdpSocket::bind("0.0.0.0:0").await?
+        };
+        socket.connect(addr).await?;
+        Ok(socket)
+    }
+}
+
+impl Tracker for UdpTracker {
+    fn announce<'a>(
+        &'a self,
+        info_hash: &'a HashPiece,
+        peer_id: &'a HashPiece,
+        port: u16,
+        event: Event,
+        stats: AnnounceStats,
+    ) -> BoxFuture<'a, Result<AnnounceResponse>> {
+        Box::pin(UdpTracker::announce(
+            self, info_hash, peer_id, port, event, stats,
+        ))
+    }
+
+    fn scrape<'a>(
+        &'a self,
+        info_hashes: &'a [HashPiece],
+    ) -> BoxFuture<'a, Result<HashMap<HashPiece, ScrapeStats>>> {
+        Box::pin(UdpTracker::scrape(self, info_hashes))
+    }
+}
+
+fn request_header(connection_id: u64, action: u32, transaction_id: u32) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(REQUEST_HEADER_LEN);
+    buf.put_u64(connection_id);
+    buf.put_u32(action);
+    buf.put_u32(transaction_id);
+    buf
+}
+
+fn announce_payload(
+    info_hash: &HashPiece,
+    peer_id: &HashPiece,
+    port: u16,
+    event: Event,
+    stats: AnnounceStats,
+    key: u32,
+) -> Vec<u8> {
+    let mut buf = Vec::new();
+    buf.put_slice(info_hash.as_ref());
+    buf.put_slice(peer_id.as_ref());
+    buf.put_u64(stats.downloaded);
+    buf.put_u64(stats.left);
+    buf.put_u64(stats.uploaded);
+    buf.put_u32(match event {
+        Event::None => 0,
+        Event::Completed => 1,
+        Event::Started => 2,
+        Event::Stopped => 3,
+    });
+    // let the tracker use the source ip of the packet
+    buf.put_u32(0);
+    buf.put_u32(key);
+    // default number of peers
+    buf.put_i32(-1);
+    buf.put_u16(port);
+    buf
+}
+
+/// Check the header of a response and return its body.
+fn parse_response(buf: &[u8], action: u32, transaction_id: u32) -> Result<&[u8]> {
+    if buf.len() < RESPONSE_HEADER_LEN {
+        return Err(Error::InvalidResponse("udp response is too short"));
+    }
+    let (mut header, body) = buf.split_at(RESPONSE_HEADER_LEN);
+    let response_action = header.get_u32();
+    if header.get_u32() != transaction_id {
+        return Err(Error::InvalidResponse("transaction id mismatch"));
+    }
+    if response_action == ACTION_ERROR {
+        return Err(Error::Failure(String::from_utf8_lossy(body).into_owned()));
+    }
+    if response_action != action {
+        return Err(Error::InvalidResponse("unexpected action"));
+    }
+    Ok(body)
+}
+
+fn parse_announce(mut body: &[u8], entry_len: usize) -> Result<AnnounceResponse> {
+    if body.len() < 12 {
+        return Err(Error::InvalidResponse("udp announce response is too short"));
+    }
+    let interval = body.get_u32();
+    let incomplete = body.get_u32();
+    let complete = body.get_u32();
+    let peers = from_compact_list(body, entry_len).ok_or(Error::InvalidPeers)?;
+    Ok(AnnounceResponse {
+        interval: Duration::from_secs(interval as u64),
+        min_interval: None,
+        complete: Some(complete as u64),
+        incomplete: Some(incomplete as u64),
+        peers,
+    })
+}
+
+fn parse_scrape(
+    mut body: &[u8],
+    info_hashes: &[HashPiece],
+) -> Result<HashMap<HashPiece, ScrapeStats>> {
+    if body.len() != info_hashes.len() * 12 {
+        return Err(Error::InvalidResponse(
+            "udp scrape response length mismatch",
+        ));
+    }
+    let mut stats = HashMap::new();
+    for info_hash in info_hashes {
+        let complete = body.get_u32() as u64;
+        let downloaded = body.get_u32() as u64;
+        let incomplete = body.get_u32() as u64;
+        stats.insert(
+            info_hash.clone(),
+            ScrapeStats {
+                complete,
+                downloaded,
+                incomplete,
+            },
+        );
+    }
+    Ok(stats)
+}
+
+/// Send the request of `action` with a fresh connection id and return the body of its response,
+/// unanswered packets are retransmitted with exponential backoff.
+async fn transact(socket: &UdpSocket, action: u32, payload: &[u8]) -> Result<Vec<u8>> {
+    let mut connection: Option<(u64, Instant)> = None;
+    let mut transaction_id = random();
+    let mut retries = 0;
+    let mut buf = vec![0; MAX_PACKET_LEN];
+    loop {
+        let request = match connection {
+            Some((connection_id, connected_at)) if connected_at.elapsed() < CONNECTION_ID_TTL => {
+                let mut request = request_header(connection_id, action, transaction_id);
+                request.extend_from_slice(payload);
+                request
+            }
+            _ => {
+                connection = None;
+                request_header(PROTOCOL_ID, ACTION_CONNECT, transaction_id)
+            }
+        };
+        socket.send(&request).await?;
+        let deadline = Instant::now() + Duration::from_secs(15 << retries);
+        let len = match recv_transaction(socket, &mut buf, transaction_id, deadline).await {
+            Ok(len) => len,
+            Err(e) if e.kind() == ErrorKind::TimedOut => {
+                if retries == MAX_RETRIES {
+                    return Err(Error::Timeout);
+                }
+                retries += 1;
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        match connection {
+            Some(_) => return Ok(parse_response(&buf[..len], action, transaction_id)?.to_vec()),
+            None => {
+                let mut body = parse_response(&buf[..len], ACTION_CONNECT, transaction_id)?;
+                if body.len() < 8 {
+                    return Err(Error::InvalidResponse("udp connect response is too short"));
+                }
+                connection = Some((body.get_u64(), Instant::now()));
+                transaction_id = random();
+            }
+        }
+    }
+}
+
+/// Receive the response of `transaction_id` before `deadline`.
+/// Other packets, such as late responses to a retransmitted connect, are ignored.
+async fn recv_transaction(
+    socket: &UdpSocket,
+    buf: &mut [u8],
+    transaction_id: u32,
+    deadline: Instant,
+) -> io::Result<usize> {
+    loop {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        let len = io::timeout(timeout, socket.recv(buf)).await?;
+        if len >= RESPONSE_HEADER_LEN && buf[4..8] == transaction_id.to_be_bytes() {
+            return Ok(len);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metainfo::PeerAddress;
+    use async_std::task::{block_on, spawn};
+
+    #[test]
+    fn test_udp_packing() {
+        let info_hash = HashPiece::new([1; 20]);
+        let peer_id = HashPiece::new([2; 20]);
+        let stats = AnnounceStats {
+            uploaded: 3,
+            downloaded: 4,
+            left: 5,
+        };
+        let mut request = request_header(0x1122_3344_5566_7788, ACTION_ANNOUNCE, 0xaabb_ccdd);
+        request.extend(announce_payload(
+            &info_hash,
+            &peer_id,
+            6881,
+            Event::Started,
+            stats,
+            0x0102_0304,
+        ));
+        assert_eq!(request.len(), 98);
+        assert_eq!(
+            request[..8],
+            [0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88]
+        );
+        assert_eq!(request[8..12], [0, 0, 0, 1]);
+        assert_eq!(request[12..16], [0xaa, 0xbb, 0xcc, 0xdd]);
+        assert_eq!(request[16..36], [1; 20]);
+        assert_eq!(request[36..56], [2; 20]);
+        assert_eq!(request[56..64], [0, 0, 0, 0, 0, 0, 0, 4]);
+        assert_eq!(request[64..72], [0, 0, 0, 0, 0, 0, 0, 5]);
+        assert_eq!(request[72..80], [0, 0, 0, 0, 0, 0, 0, 3]);
+        assert_eq!(request[80..84], [0, 0, 0, 2]);
+        assert_eq!(request[84..88], [0; 4]);
+        assert_eq!(request[88..92], [1, 2, 3, 4]);
+        assert_eq!(request[92..96], [0xff; 4]);
+        assert_eq!(request[96..], [0x1a, 0xe1]);
+
+        let connect = request_header(PROTOCOL_ID, ACTION_CONNECT, 1);
+        assert_eq!(
+            connect,
+            [0, 0, 0x04, 0x17, 0x27, 0x10, 0x19, 0x80, 0, 0, 0, 0, 0, 0, 0, 1]
+        );
+
+        let response = [
+            0, 0, 0, 1, 0, 0, 0, 7, 0, 0, 0x07, 0x08, 0, 0, 0, 2, 0, 0, 0, 3, 1, 2, 3, 4, 0x04,
+            0xd2,
+        ];
+        let body = parse_response(&response, ACTION_ANNOUNCE, 7).unwrap();
+        let announce = parse_announce(body, ADDRESS_V4_LEN).unwrap();
+        assert_eq!(announce.interval, Duration::from_secs(1800));
+        assert_eq!(announce.incomplete, Some(2));
+        assert_eq!(announce.complete, Some(3));
+        assert_eq!(
+            announce.peers,
+            vec![PeerAddress("1.2.3.4:1234".parse().unwrap())]
+        );
+        assert!(parse_response(&response, ACTION_ANNOUNCE, 8).is_err());
+        assert!(matches!(
+            parse_response(b"\x00\x00\x00\x03\x00\x00\x00\x07denied", ACTION_ANNOUNCE, 7),
+            Err(Error::Failure(message)) if message == "denied"
+        ));
+
+        let info_hashes = [HashPiece::new([1; 20]), HashPiece::new([2; 20])];
+        let body = [
+            0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0, 4, 0, 0, 0, 5, 0, 0, 0, 6,
+        ];
+        let stats = parse_scrape(&body, &info_hashes).unwrap();
+        assert_eq!(
+            stats[&info_hashes[1]],
+            ScrapeStats {
+                complete: 4,
+                downloaded: 5,
+                incomplete: 6
+            }
+        );
+        assert!(parse_scrape(&body[1..], &info_hashes).is_err());
+    }
+
+    #[test]
+    fn test_udp_announce() {
+        block_on(async {
+            let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let url =
+                Url::parse(&format!("udp://{}/announce", server.local_addr().unwrap())).unwrap();
+            let handle = spawn(async move {
+                let mut buf = vec![0; MAX_PACKET_LEN];
+                let (len, peer) = server.recv_from(&mut buf).await.unwrap();
+                assert_eq!(len, REQUEST_HEADER_LEN);
+                assert_eq!(buf[..8], PROTOCOL_ID.to_be_bytes());
+                // a stray packet of another transaction is ignored
+                let mut stray = vec![0, 0, 0, 0];
+                stray.extend_from_slice(&buf[12..16]);
+                stray[7] ^= 1;
+                stray.extend_from_slice(&7u64.to_be_bytes());
+                server.send_to(&stray, peer).await.unwrap();
+                let mut response = vec![0, 0, 0, 0];
+                response.extend_from_slice(&buf[12..16]);
+                response.extend_from_slice(&42u64.to_be_bytes());
+                server.send_to(&response, peer).await.unwrap();
+
+                let (len, peer) = server.recv_from(&mut buf).await.unwrap();
+                assert_eq!(len, 98);
+                assert_eq!(buf[..8], 42u64.to_be_bytes());
+                let mut response = vec![0, 0, 0, 1];
+                response.extend_from_slice(&buf[12..16]);
+                response.extend_from_slice(&[0, 0, 0x07, 0x08, 0, 0, 0, 0, 0, 0, 0, 1]);
+                // more peers than fit in 4KiB
+                for _ in 0..1000 {
+                    response.extend_from_slice(&[5, 6, 7, 8, 0x1a, 0xe1]);
+                }
+                server.send_to(&response, peer).await.unwrap();
+            });
+            let tracker = UdpTracker::new(url).unwrap();
+            let response = tracker
+                .announce(
+                    &HashPiece::rand_new(),
+                    &HashPiece::rand_new(),
+                    6881,
+                    Event::Started,
+                    AnnounceStats::default(),
+                )
+                .await
+                .unwrap();
+            handle.await;
+            assert_eq!(response.complete, Some(1));
+            assert_eq!(
+                response.peers,
+                vec![PeerAddress("5.6.7.8:6881".parse().unwrap()); 1000]
+            );
+        })
+    }
+}